@@ -0,0 +1,225 @@
+//! Headless blobpaint renderer.
+//!
+//! Steps a BlobCanvas the same way the site does every animation frame
+//! (tick then fill_draw_buffer) and writes each frame out as a binary ppm,
+//! or with --full rasterizes every pixel of every frame without dithering,
+//! so artwork can be rendered in batch jobs without a browser.
+//! --gif instead captures one seamless loop of the threshold animation,
+//! with frame k of n rendered at phase k / n of the cycle.
+//! Frames use the palette saved with the document unless --palette gives
+//! a GIMP .gpl or hex palette file.
+//! --image starts from a png instead of a document, dark lines becoming blobs.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use blobrust::{BlobCanvas, ImportSettings, Palette, MAX_DIMENSION};
+
+const USAGE : &str = "usage: blobpaint [--input DOC | --image PNG | --width W --height H [--seed S]] [--palette FILE] ([--frames N] [--fps F] [--full] --out DIR | --gif FILE)";
+
+struct Config {
+  input : Option<PathBuf>,
+  image : Option<PathBuf>,
+  width : u32,
+  height : u32,
+  seed : Option<u64>,
+  palette : Option<PathBuf>,
+  frames : u32,
+  fps : u32,
+  full : bool,
+  out : Option<PathBuf>,
+  gif : Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Config, String> {
+  let mut width = 256;
+  let mut height = 200;
+  let mut frames = 50;
+  let mut fps = 50;
+  let mut seed = None;
+  let mut full = false;
+  let mut input = None;
+  let mut image = None;
+  let mut palette = None;
+  let mut out = None;
+  let mut gif = None;
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+    match arg.as_str() {
+      "--input" => input = Some(PathBuf::from(value()?)),
+      "--image" => image = Some(PathBuf::from(value()?)),
+      "--width" => width = parse_num(&value()?)?,
+      "--height" => height = parse_num(&value()?)?,
+      "--seed" => seed = Some(value()?.parse().map_err(|_| "expected a number for --seed".to_owned())?),
+      "--palette" => palette = Some(PathBuf::from(value()?)),
+      "--frames" => frames = parse_num(&value()?)?,
+      "--fps" => fps = parse_num(&value()?)?,
+      "--full" => full = true,
+      "--out" => out = Some(PathBuf::from(value()?)),
+      "--gif" => gif = Some(PathBuf::from(value()?)),
+      "-h" | "--help" => {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      _ => return Err(format!("unexpected argument {}", arg)),
+    }
+  }
+
+  if width == 0 || height == 0 || fps == 0 {
+    return Err("width, height and fps must be non-zero".to_owned());
+  }
+
+  if input.is_some() && image.is_some() {
+    return Err("only one of --input or --image can be given".to_owned());
+  }
+
+  if out.is_none() && gif.is_none() {
+    return Err("one of --out or --gif is required".to_owned());
+  }
+
+  if width > MAX_DIMENSION || height > MAX_DIMENSION {
+    return Err(format!("width and height must be at most {}", MAX_DIMENSION));
+  }
+
+  Ok(Config {
+    input : input,
+    image : image,
+    width : width,
+    height : height,
+    seed : seed,
+    palette : palette,
+    frames : frames,
+    fps : fps,
+    full : full,
+    out : out,
+    gif : gif,
+  })
+}
+
+fn parse_num(s : &str) -> Result<u32, String> {
+  s.parse().map_err(|_| format!("expected a number, got {}", s))
+}
+
+/// Persistent rgba image, updated incrementally from the draw buffer
+/// in the same way the site paints onto its html canvas.
+struct Frame {
+  width : u32,
+  height : u32,
+  palette : Vec<u8>,
+  rgba : Vec<u8>,
+}
+
+impl Frame {
+  fn new(canvas : &BlobCanvas) -> Self {
+    let (width, height) = (canvas.width(), canvas.height());
+    Frame {
+      width : width,
+      height : height,
+      palette : canvas.palette().to_rgb_padded(canvas.get_palette_size()),
+      rgba : vec![0; width as usize * height as usize * 4],
+    }
+  }
+
+  fn set(&mut self, x : u32, y : u32, col : u8) {
+    if x < self.width && y < self.height {
+      let i = (y * self.width + x) as usize * 4;
+      let c = col as usize * 3;
+      self.rgba[i..i+3].copy_from_slice(&self.palette[c..c+3]);
+    }
+  }
+
+  /// Mirror the 2x2 fillRect the site uses for each draw point.
+  fn plot(&mut self, x : u32, y : u32, col : u8) {
+    self.set(x, y, col);
+    self.set(x + 1, y, col);
+    self.set(x, y + 1, col);
+    self.set(x + 1, y + 1, col);
+  }
+
+  fn write_ppm(&self, path : &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+    for pixel in self.rgba.chunks(4) {
+      writer.write_all(&pixel[..3])?;
+    }
+    writer.flush()
+  }
+}
+
+fn run(config : Config) -> io::Result<()> {
+  let mut canvas = match (&config.input, &config.image) {
+    (Some(path), _) => {
+      let data = fs::read(path)?;
+      BlobCanvas::deserialize(&data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?
+    },
+    (_, Some(path)) => {
+      let data = fs::read(path)?;
+      BlobCanvas::from_png(&data, &ImportSettings::default())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?
+    },
+    _ => match config.seed {
+      Some(seed) => BlobCanvas::new_with_seed(config.width, config.height, seed),
+      None => BlobCanvas::new(config.width, config.height),
+    }.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?,
+  };
+
+  if let Some(path) = &config.palette {
+    let text = fs::read_to_string(path)?;
+    let palette = Palette::parse(&text)
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+    canvas.set_palette(&palette);
+  }
+
+  if let Some(path) = &config.gif {
+    // One full threshold cycle, with the shimmer from dithering like the site.
+    return fs::write(path, canvas.encode_gif(true));
+  }
+
+  let out = config.out.as_ref().expect("checked in parse_args");
+  fs::create_dir_all(out)?;
+
+  let mut frame = Frame::new(&canvas);
+
+  // The browser canvas fills in over the first few seconds,
+  // we start from a complete image instead.
+  canvas.render_rgba(&mut frame.rgba, true);
+
+  let tick_us = 1_000_000 / config.fps;
+  for i in 0..config.frames {
+    canvas.tick(tick_us);
+    if config.full {
+      canvas.render_rgba(&mut frame.rgba, false);
+    }
+    else {
+      canvas.fill_draw_buffer();
+      for (x, y, col) in canvas.draw_buffer().iter() {
+        frame.plot(x, y, col);
+      }
+    }
+
+    frame.write_ppm(&out.join(format!("frame_{:05}.ppm", i)))?;
+  }
+
+  Ok(())
+}
+
+fn main() {
+  let config = match parse_args() {
+    Ok(config) => config,
+    Err(e) => {
+      eprintln!("{}\n{}", e, USAGE);
+      process::exit(2);
+    }
+  };
+
+  if let Err(e) = run(config) {
+    eprintln!("blobpaint: {}", e);
+    process::exit(1);
+  }
+}
@@ -1,26 +1,29 @@
 use wasm_bindgen::prelude::*;
 
 use super::{PointData, CanvasApi};
-use super::utils::{clamp_unit, sqr, lerpk, normalize};
+use super::utils::{clamp_unit, sqr, lerpk, normalize, wrap_unit, wrap_half};
 
 #[wasm_bindgen]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 pub enum BrushType {
+  // Default to basic paintbrush.
+  #[default]
   Inv,
   Outliner,
   Smudger,
   Colorer,
+  Wobbler,
+  LineWeight,
 }
 
-impl Default for BrushType {
-  // Default to basic paintbrush.
-  fn default() -> Self {
-    BrushType::Inv
-  }
-}
+/// Largest wobble amplitude a Wobbler paints, relative to the layer's wave.
+pub const MAX_WOBBLE_AMP : f32 = 4.0;
+
+/// Heaviest line weight a LineWeight brush paints, relative to the canvas outline width.
+pub const MAX_LINE_WEIGHT : f32 = 8.0;
 
 #[wasm_bindgen]
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Brush {
   // We can only keep c-style enums when we generate wasm.
   // Emulate a union with a series of optional structs.
@@ -29,7 +32,50 @@ pub struct Brush {
   pub outliner : Option<Outliner>,
   pub smudger : Option<Smudger>,
   pub colorer : Option<Colorer>,
+  pub wobbler : Option<Wobbler>,
+  pub line_weight : Option<LineWeight>,
   pub size : f32,
+  pub dynamics : Dynamics,
+}
+
+/// How much pen input modulates a brush per stamp.
+/// Each amount is 0 for no effect, all default to 0.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Dynamics {
+  /// At 1 size scales with pressure.
+  pub pressure_size : f32,
+  /// At 1 the paintbrush mult scales with pressure.
+  pub pressure_mult : f32,
+  /// At 1 the paintbrush falloff curve doubles at zero pressure, for a harder tip.
+  pub pressure_curve : f32,
+  /// At 1 the colorer, wobbler and line weight strength scales with pressure.
+  pub pressure_strength : f32,
+  /// At 1 a fully tilted pen doubles the size.
+  pub tilt_size : f32,
+  /// At 1 the fastest strokes shrink to nothing.
+  pub velocity_size : f32,
+}
+
+/// Pen state for a single stamp.
+#[derive(Copy, Clone, Debug)]
+pub struct StampInput {
+  /// 0 to 1, 1 for a mouse.
+  pub pressure : f32,
+  /// 0 upright to 1 flat against the surface.
+  pub tilt : f32,
+  /// 0 still to 1 fast, see Stroke.
+  pub velocity : f32,
+}
+
+impl Default for StampInput {
+  fn default() -> Self {
+    StampInput {
+      pressure : 1.0,
+      tilt : 0.0,
+      velocity : 0.0,
+    }
+  }
 }
 
 #[wasm_bindgen]
@@ -63,6 +109,7 @@ impl Brush {
     brush.brush_type = BrushType::Colorer;
     brush.colorer = Some(Colorer {
       color : color,
+      strength : 1.0,
     });
 
     brush.size = size;
@@ -70,6 +117,37 @@ impl Brush {
     brush
   }
   
+  /// Paints how strongly the threshold wave moves each point, `amplitude` 0 holds
+  /// still and 1 matches the layer, and its offset as a fraction of a cycle.
+  pub fn new_wobbler(size : f32, amplitude : f32, phase : f32) -> Self {
+    let mut brush = Brush::default();
+    brush.brush_type = BrushType::Wobbler;
+    brush.wobbler = Some(Wobbler {
+      amplitude : amplitude.clamp(0.0, MAX_WOBBLE_AMP),
+      phase : wrap_unit(phase),
+      strength : 1.0,
+    });
+
+    brush.size = size;
+
+    brush
+  }
+
+  /// Paints how thick outlines are, `weight` 0 for none and 1 for the canvas
+  /// outline width.
+  pub fn new_line_weight(size : f32, weight : f32) -> Self {
+    let mut brush = Brush::default();
+    brush.brush_type = BrushType::LineWeight;
+    brush.line_weight = Some(LineWeight {
+      weight : weight.clamp(0.0, MAX_LINE_WEIGHT),
+      strength : 1.0,
+    });
+
+    brush.size = size;
+
+    brush
+  }
+
   pub fn new_smudger(size : f32) -> Self {
     let mut brush = Brush::default();
     brush.brush_type = BrushType::Smudger;
@@ -113,6 +191,51 @@ impl Brush {
     }
   }
 
+  pub fn set_wobble_amplitude(&mut self, amplitude : f32) {
+    match self.brush_type {
+      BrushType::Wobbler => {self.wobbler.as_mut().unwrap().amplitude = amplitude.clamp(0.0, MAX_WOBBLE_AMP)},
+      _ => {},
+    }
+  }
+
+  pub fn set_wobble_phase(&mut self, phase : f32) {
+    match self.brush_type {
+      BrushType::Wobbler => {self.wobbler.as_mut().unwrap().phase = wrap_unit(phase)},
+      _ => {},
+    }
+  }
+
+  pub fn set_line_weight(&mut self, weight : f32) {
+    match self.brush_type {
+      BrushType::LineWeight => {self.line_weight.as_mut().unwrap().weight = weight.clamp(0.0, MAX_LINE_WEIGHT)},
+      _ => {},
+    }
+  }
+
+  pub fn set_pressure_size(&mut self, amount : f32) {
+    self.dynamics.pressure_size = amount;
+  }
+
+  pub fn set_pressure_mult(&mut self, amount : f32) {
+    self.dynamics.pressure_mult = amount;
+  }
+
+  pub fn set_pressure_curve(&mut self, amount : f32) {
+    self.dynamics.pressure_curve = amount;
+  }
+
+  pub fn set_pressure_strength(&mut self, amount : f32) {
+    self.dynamics.pressure_strength = amount;
+  }
+
+  pub fn set_tilt_size(&mut self, amount : f32) {
+    self.dynamics.tilt_size = amount;
+  }
+
+  pub fn set_velocity_size(&mut self, amount : f32) {
+    self.dynamics.velocity_size = amount;
+  }
+
   pub fn set_outliner_height(&mut self, height: f32) {
     match self.brush_type {
       BrushType::Outliner => {self.outliner.as_mut().unwrap().height = height},
@@ -159,6 +282,60 @@ impl Brush {
   }
 }
 
+impl Brush {
+  /// Copy of the brush with dynamics applied for one stamp.
+  pub fn modulated(&self, input : &StampInput) -> Brush {
+    let d = &self.dynamics;
+    let pressure = clamp_unit(input.pressure);
+
+    let mut brush = self.clone();
+    brush.size = self.size
+      * scale_by(d.pressure_size, pressure)
+      * (1.0 + d.tilt_size * clamp_unit(input.tilt))
+      * scale_by(d.velocity_size, 1.0 - clamp_unit(input.velocity));
+
+    if let Some(paintbrush) = brush.paintbrush.as_mut() {
+      paintbrush.mult *= scale_by(d.pressure_mult, pressure);
+      paintbrush.curve *= 1.0 + d.pressure_curve * (1.0 - pressure);
+    }
+
+    if let Some(colorer) = brush.colorer.as_mut() {
+      colorer.strength *= scale_by(d.pressure_strength, pressure);
+    }
+
+    if let Some(wobbler) = brush.wobbler.as_mut() {
+      wobbler.strength *= scale_by(d.pressure_strength, pressure);
+    }
+
+    if let Some(line_weight) = brush.line_weight.as_mut() {
+      line_weight.strength *= scale_by(d.pressure_strength, pressure);
+    }
+
+    brush
+  }
+
+  /// Copy of the brush as seen through a symmetry transform.
+  /// Only the smudge direction isn't symmetric already.
+  pub fn transformed(&self, m : &[f32; 4]) -> Brush {
+    let mut brush = self.clone();
+    if let Some(smudger) = brush.smudger.as_mut() {
+      let (x, y) = (smudger.smudge_vec_x, smudger.smudge_vec_y);
+      smudger.smudge_vec_x = m[0] * x + m[1] * y;
+      smudger.smudge_vec_y = m[2] * x + m[3] * y;
+      let (nx, ny) = normalize(smudger.smudge_vec_x, smudger.smudge_vec_y);
+      smudger.smudge_vec_x_norm = nx;
+      smudger.smudge_vec_y_norm = ny;
+    }
+
+    brush
+  }
+}
+
+/// Blend from 1 at amount 0 to `value` at amount 1.
+fn scale_by(amount : f32, value : f32) -> f32 {
+  1.0 - amount * (1.0 - value)
+}
+
 impl<'t> Brush {
   pub fn sample_canvas(&self, dx: f32, dy: f32, mut api: CanvasApi<'t>, remove: bool)
   {
@@ -200,7 +377,7 @@ impl<'t> Brush {
         let colorer_config = self.colorer.as_ref().unwrap();
         let delta = colorer_config.color - point_data.color_band;
 
-        let k = (1.0 - curve * (dist / rad).sqrt()) * mult;
+        let k = (1.0 - curve * (dist / rad).sqrt()) * mult * colorer_config.strength;
         if k > 0.0 {
           if delta > 0.0 {
             point_data.color_band = (point_data.color_band + k).min(colorer_config.color);
@@ -213,7 +390,15 @@ impl<'t> Brush {
       BrushType::Smudger => {
         let smudge = self.smudger.as_ref().unwrap();
         smudge.apply_smudge(dx, dy, api);
-      }
+      },
+      BrushType::Wobbler => {
+        let wobbler = self.wobbler.as_ref().unwrap();
+        wobbler.apply_point_mut(dx, dy, api.get_mut(), self.size);
+      },
+      BrushType::LineWeight => {
+        let line_weight = self.line_weight.as_ref().unwrap();
+        line_weight.apply_point_mut(dx, dy, api.get_mut(), self.size);
+      },
     }
   }
 }
@@ -271,7 +456,9 @@ pub struct Outliner {
 #[derive(Copy, Clone, Debug)]
 pub struct Smudger {
   smudge_dist_mult : f32,
+  #[allow(dead_code)]
   mult: f32,
+  #[allow(dead_code)]
   curve: f32,
   smudge_vec_x: f32,
   smudge_vec_y: f32,
@@ -298,9 +485,13 @@ impl<'t> Smudger {
         Some(source_smudge) => {
           let k = 2.0 * (1.0 + offset_dist);
 
-          let mut cur = api.get_mut();
+          let cur = api.get_mut();
           cur.thresh_band = lerpk(cur.thresh_band, source_smudge.thresh_band, k);
           cur.color_band = lerpk(cur.color_band, source_smudge.color_band, k);
+          cur.wobble_amp = lerpk(cur.wobble_amp, source_smudge.wobble_amp, k);
+          let phase_delta = wrap_half(source_smudge.wobble_phase - cur.wobble_phase);
+          cur.wobble_phase = wrap_unit(lerpk(cur.wobble_phase, cur.wobble_phase + phase_delta, k));
+          cur.line_weight = lerpk(cur.line_weight, source_smudge.line_weight, k);
         }
         _ => {},
       }
@@ -312,4 +503,55 @@ impl<'t> Smudger {
 #[derive(Copy, Clone, Debug)]
 pub struct Colorer {
   color : f32,
+  strength : f32,
+}
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug)]
+pub struct Wobbler {
+  amplitude : f32,
+  phase : f32,
+  strength : f32,
+}
+
+impl Wobbler {
+  /// Step the point's wobble towards the brush's, falling off like the colorer.
+  /// Phase goes the short way round the cycle.
+  pub fn apply_point_mut(&self, dx : f32, dy : f32, p : &mut PointData, size : f32) {
+    let dist = (sqr(dx) + sqr(dy)).sqrt();
+    let rad = size / 2.0;
+    let k = (1.0 - (dist / rad).sqrt()) * 0.625 * self.strength;
+    if k > 0.0 {
+      p.wobble_amp = step_towards(p.wobble_amp, self.amplitude, k);
+      let phase_delta = wrap_half(self.phase - p.wobble_phase);
+      p.wobble_phase = wrap_unit(p.wobble_phase + phase_delta.clamp(-k, k));
+    }
+  }
+}
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug)]
+pub struct LineWeight {
+  weight : f32,
+  strength : f32,
+}
+
+impl LineWeight {
+  pub fn apply_point_mut(&self, dx : f32, dy : f32, p : &mut PointData, size : f32) {
+    let dist = (sqr(dx) + sqr(dy)).sqrt();
+    let rad = size / 2.0;
+    let k = (1.0 - (dist / rad).sqrt()) * 0.625 * self.strength;
+    if k > 0.0 {
+      p.line_weight = step_towards(p.line_weight, self.weight, k);
+    }
+  }
+}
+
+fn step_towards(x : f32, target : f32, k : f32) -> f32 {
+  if target > x {
+    (x + k).min(target)
+  }
+  else {
+    (x - k).max(target)
+  }
 }
\ No newline at end of file
@@ -0,0 +1,57 @@
+use std::borrow::Cow;
+
+use gif::{Encoder, Frame, Repeat};
+
+use super::BlobCanvas;
+
+/// Gif frame delay in hundredths of a second, matches the 50fps the site records at.
+pub const FRAME_DELAY_CS : u16 = 2;
+
+/// Upper bound on frames in a single capture, very slow waves get played back faster
+/// rather than producing enormous files.
+pub const MAX_FRAMES : u32 = 3000;
+
+/// Encode exactly one loop of the layers' threshold waves into a looping gif.
+///
+/// Frame k of n is rendered k / n of the way through the loop rather than from the
+/// time accumulated by tick, so the last frame leads straight back into the first.
+pub fn encode_gif(canvas : &mut BlobCanvas, dither : bool) -> Vec<u8> {
+  let palette = canvas.palette().to_rgb_padded(canvas.get_palette_size());
+
+  let (width, height) = (canvas.width(), canvas.height());
+  let frame_us = FRAME_DELAY_CS as f64 * 10_000.0;
+  let period_us = canvas.get_cycle_length_us();
+
+  let frame_count = if period_us.is_finite() {
+    ((period_us / frame_us).round() as u32).clamp(1, MAX_FRAMES)
+  }
+  else {
+    // No animation, a single still frame.
+    1
+  };
+
+  let mut out = Vec::new();
+  {
+    let mut encoder = Encoder::new(&mut out, width as u16, height as u16, &palette)
+      .expect("encoding to memory can't fail");
+    encoder.set_repeat(Repeat::Infinite)
+      .expect("encoding to memory can't fail");
+
+    let mut buffer = vec![0; (width * height) as usize];
+    for i in 0..frame_count {
+      canvas.render_frame_indexed(i, frame_count, &mut buffer, dither);
+
+      let frame = Frame {
+        width : width as u16,
+        height : height as u16,
+        delay : FRAME_DELAY_CS,
+        buffer : Cow::Borrowed(&buffer),
+        ..Frame::default()
+      };
+      encoder.write_frame(&frame)
+        .expect("encoding to memory can't fail");
+    }
+  }
+
+  out
+}
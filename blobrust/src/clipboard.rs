@@ -0,0 +1,150 @@
+use super::pointdata::{PointData, PointDataStore, DirtyRect};
+use super::selection::Selection;
+use super::history::UndoHistory;
+
+/// A block of points copied from a layer, shaped by the selection it came from.
+#[derive(Clone, Debug)]
+pub struct Clip {
+  width : u32,
+  height : u32,
+  points : Vec<PointData>,
+  mask : Selection,
+  /// Where the top left was copied from.
+  x : i32,
+  y : i32,
+}
+
+impl Clip {
+  /// The selected points, or the whole layer without a selection.
+  /// None if nothing is selected.
+  pub fn copy(store : &PointDataStore, selection : Option<&Selection>) -> Option<Self> {
+    let rect = match selection {
+      Some(selection) => selection.bounds(),
+      None => DirtyRect::all_dirty(store.get_width(), store.get_height()),
+    };
+    if rect.is_empty() {
+      return None;
+    }
+
+    let mask = Selection::from_fn(rect.width(), rect.height(), |x, y| {
+      selection.is_none_or(|s| s.contains(x + rect.min_x, y + rect.min_y))
+    });
+
+    Some(Clip {
+      width : rect.width(),
+      height : rect.height(),
+      points : store.copy_rect(&rect),
+      mask : mask,
+      x : rect.min_x as i32,
+      y : rect.min_y as i32,
+    })
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// Point at (x, y) within the clip, None outside its shape.
+  pub fn get(&self, x : u32, y : u32) -> Option<PointData> {
+    if self.mask.contains(x, y) {
+      Some(self.points[(y * self.width + x) as usize])
+    }
+    else {
+      None
+    }
+  }
+
+  pub fn flip_hoz(&mut self) {
+    let w = self.width;
+    self.remap(|x, y| (w - x - 1, y));
+  }
+
+  pub fn flip_vert(&mut self) {
+    let h = self.height;
+    self.remap(|x, y| (x, h - y - 1));
+  }
+
+  fn remap<F : Fn(u32, u32) -> (u32, u32)>(&mut self, source : F) {
+    let mut points = Vec::with_capacity(self.points.len());
+    for y in 0..self.height {
+      for x in 0..self.width {
+        let (sx, sy) = source(x, y);
+        points.push(self.points[(sy * self.width + sx) as usize]);
+      }
+    }
+
+    let mask = &self.mask;
+    self.mask = Selection::from_fn(self.width, self.height, |x, y| {
+      let (sx, sy) = source(x, y);
+      mask.contains(sx, sy)
+    });
+    self.points = points;
+  }
+}
+
+/// A clip pasted onto a layer that can still be moved or flipped.
+///
+/// It is written into the layer while floating, so it renders like anything
+/// else, keeping what was underneath to put back when it moves.
+pub struct FloatingPaste {
+  pub clip : Clip,
+  pub layer : usize,
+  /// Top left, in pixels, may be off the canvas.
+  pub x : i32,
+  pub y : i32,
+  /// Part of the canvas covered, and what was there before.
+  rect : DirtyRect,
+  under : Vec<PointData>,
+}
+
+impl FloatingPaste {
+  /// Where the clip was copied from.
+  pub fn new(clip : Clip, layer : usize) -> Self {
+    let (x, y) = (clip.x, clip.y);
+    FloatingPaste {
+      clip : clip,
+      layer : layer,
+      x : x,
+      y : y,
+      rect : DirtyRect::default(),
+      under : Vec::new(),
+    }
+  }
+
+  /// Write the clip onto the layer at its position, saving what was there.
+  pub fn place(&mut self, store : &mut PointDataStore, history : &mut UndoHistory) {
+    let (w, h) = (store.get_width() as i32, store.get_height() as i32);
+    let min_x = self.x.clamp(0, w);
+    let min_y = self.y.clamp(0, h);
+    self.rect = DirtyRect {
+      min_x : min_x as u32,
+      min_y : min_y as u32,
+      max_x : (self.x + self.clip.width as i32).clamp(min_x, w) as u32,
+      max_y : (self.y + self.clip.height as i32).clamp(min_y, h) as u32,
+    };
+
+    history.record(self.layer, store, &self.rect);
+    self.under = store.copy_rect(&self.rect);
+    store.extend_dirty(self.rect);
+    for y in self.rect.min_y..self.rect.max_y {
+      for x in self.rect.min_x..self.rect.max_x {
+        let (cx, cy) = ((x as i32 - self.x) as u32, (y as i32 - self.y) as u32);
+        match self.clip.get(cx, cy) {
+          Some(p) => *store.get_mut(x, y) = p,
+          _ => {},
+        }
+      }
+    }
+  }
+
+  /// Put back what was under the clip.
+  pub fn lift(&mut self, store : &mut PointDataStore) {
+    store.swap_rect(&self.rect, &mut self.under);
+    self.rect = DirtyRect::default();
+    self.under.clear();
+  }
+}
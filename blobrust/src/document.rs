@@ -0,0 +1,559 @@
+//! Binary save format for a BlobCanvas.
+//!
+//! Everything is little endian.
+//!
+//! magic          [u8; 4]  "BLOB"
+//! version        u16
+//! width          u32
+//! height         u32
+//! t              u32
+//! seed           u64
+//! layer_count    u32
+//! active_layer   u32
+//! background     u8
+//! outline        u8
+//! ink_count      u8
+//! inks           [u8; ink_count]
+//! outline_width  f32
+//! wrap           u8
+//! name_len       u16
+//! palette_name   [u8; name_len] utf8
+//! color_count    u16
+//! palette        [[u8; 3]; color_count] rgb
+//! layers         layer_count * layer, bottom first
+//!
+//! Each layer is
+//!
+//! thresh_base    f32
+//! thresh_t_var   f32
+//! thresh_t_mult  f32
+//! visible        u8
+//! direction      f32
+//! frequency      f32
+//! radial         u8
+//! centre_x       f32
+//! centre_y       f32
+//! waveform       u8
+//! points         width * height * point
+//!
+//! Each point is
+//!
+//! thresh_band    f32
+//! color_band     f32
+//! wobble_amp     f32
+//! wobble_phase   f32
+//! line_weight    f32
+//!
+//! Older versions are missing fields, which load with defaults
+//!
+//! version 7      no wrap
+//! version 6      no outline_width or line_weight
+//! version 5      no palette
+//! version 4      no background, outline or inks
+//! version 3      no wave settings
+//! version 2      no wobble_amp or wobble_phase
+//! version 1      a single layer, with its thresholds between height and t,
+//!                and no layer_count, active_layer or visible
+//!
+//! New fields bump FORMAT_VERSION and get a line above, older versions must keep
+//! loading with defaults for anything they don't contain. tests/fixtures holds
+//! documents saved by older versions to check against.
+
+use std::convert::TryInto;
+use std::fmt;
+
+use super::pointdata::PointData;
+use super::DEFAULT_OUTLINE_WIDTH;
+use super::drawbuffer::MAX_DIMENSION;
+use super::wave::{Wave, Waveform};
+use super::ramp::ColorRamp;
+use super::palette::{Palette, PaletteError};
+
+pub const MAGIC : [u8; 4] = *b"BLOB";
+pub const FORMAT_VERSION : u16 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocumentError {
+  BadMagic,
+  UnsupportedVersion(u16),
+  BadDimensions(u32, u32),
+  BadLayers(u32, u32),
+  BadWaveform(u8),
+  NoInks,
+  BadPalette(PaletteError),
+  UnexpectedEof,
+}
+
+impl fmt::Display for DocumentError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DocumentError::BadMagic => write!(f, "not a blobpaint document"),
+      DocumentError::UnsupportedVersion(v) => write!(f, "unsupported document version {} (newest known is {})", v, FORMAT_VERSION),
+      DocumentError::BadDimensions(w, h) => write!(f, "invalid canvas dimensions {}x{}", w, h),
+      DocumentError::BadLayers(count, active) => write!(f, "invalid active layer {} of {}", active, count),
+      DocumentError::BadWaveform(w) => write!(f, "unknown waveform {}", w),
+      DocumentError::NoInks => write!(f, "no ink colours"),
+      DocumentError::BadPalette(e) => write!(f, "{}", e),
+      DocumentError::UnexpectedEof => write!(f, "document is truncated"),
+    }
+  }
+}
+
+impl std::error::Error for DocumentError {}
+
+/// Plain data view of everything that gets saved.
+#[derive(Debug, Clone)]
+pub struct Document {
+  pub width : u32,
+  pub height : u32,
+  pub t : u32,
+  pub seed : u64,
+  pub active_layer : u32,
+  pub ramp : ColorRamp,
+  pub palette : Palette,
+  pub outline_width : f32,
+  pub wrap : bool,
+  pub layers : Vec<LayerDocument>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LayerDocument {
+  pub thresh_base : f32,
+  pub thresh_t_var : f32,
+  pub thresh_t_mult : f32,
+  pub wave : Wave,
+  pub visible : bool,
+  pub points : Vec<PointData>,
+}
+
+impl Document {
+  pub fn write(&self) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.bytes(&MAGIC);
+    w.u16(FORMAT_VERSION);
+    w.u32(self.width);
+    w.u32(self.height);
+    w.u32(self.t);
+    w.u64(self.seed);
+    w.u32(self.layers.len() as u32);
+    w.u32(self.active_layer);
+    w.u8(self.ramp.background);
+    w.u8(self.ramp.outline);
+    w.u8(self.ramp.inks.len() as u8);
+    w.bytes(&self.ramp.inks);
+    w.f32(self.outline_width);
+    w.u8(self.wrap as u8);
+    let name = self.palette.get_name();
+    w.u16(name.len() as u16);
+    w.bytes(name.as_bytes());
+    w.u16(self.palette.len() as u16);
+    w.bytes(&self.palette.to_rgb());
+    for layer in &self.layers {
+      w.f32(layer.thresh_base);
+      w.f32(layer.thresh_t_var);
+      w.f32(layer.thresh_t_mult);
+      w.u8(layer.visible as u8);
+      w.f32(layer.wave.direction);
+      w.f32(layer.wave.frequency);
+      w.u8(layer.wave.radial as u8);
+      w.f32(layer.wave.centre_x);
+      w.f32(layer.wave.centre_y);
+      w.u8(layer.wave.waveform as u8);
+      for p in &layer.points {
+        w.f32(p.thresh_band);
+        w.f32(p.color_band);
+        w.f32(p.wobble_amp);
+        w.f32(p.wobble_phase);
+        w.f32(p.line_weight);
+      }
+    }
+    w.buf
+  }
+
+  pub fn read(data : &[u8]) -> Result<Self, DocumentError> {
+    let mut r = Reader { data : data };
+    if r.bytes(4)? != MAGIC {
+      return Err(DocumentError::BadMagic);
+    }
+
+    let version = r.u16()?;
+    if version == 0 || version > FORMAT_VERSION {
+      return Err(DocumentError::UnsupportedVersion(version));
+    }
+
+    if version == 1 {
+      return read_v1(&mut r);
+    }
+
+    let width = r.u32()?;
+    let height = r.u32()?;
+    let t = r.u32()?;
+    let seed = r.u64()?;
+    let layer_count = r.u32()?;
+    let active_layer = r.u32()?;
+
+    check_dimensions(width, height)?;
+    if active_layer >= layer_count {
+      return Err(DocumentError::BadLayers(layer_count, active_layer));
+    }
+
+    let ramp = if version >= 5 { r.ramp()? } else { ColorRamp::default() };
+    let outline_width = if version >= 7 { r.f32()? } else { DEFAULT_OUTLINE_WIDTH };
+    let wrap = if version >= 8 { r.u8()? != 0 } else { false };
+    let palette = if version >= 6 { r.palette()? } else { Palette::default() };
+
+    // Not preallocated, the points check in read_points catches a corrupt count.
+    let mut layers = Vec::new();
+    for _i in 0..layer_count {
+      let thresh_base = r.f32()?;
+      let thresh_t_var = r.f32()?;
+      let thresh_t_mult = r.f32()?;
+      let visible = r.u8()? != 0;
+      let wave = if version >= 4 { r.wave()? } else { Wave::default() };
+      layers.push(LayerDocument {
+        thresh_base : thresh_base,
+        thresh_t_var : thresh_t_var,
+        thresh_t_mult : thresh_t_mult,
+        wave : wave,
+        visible : visible,
+        points : r.points(width, height, version)?,
+      });
+    }
+
+    Ok(Document {
+      width : width,
+      height : height,
+      t : t,
+      seed : seed,
+      active_layer : active_layer,
+      ramp : ramp,
+      palette : palette,
+      outline_width : outline_width,
+      wrap : wrap,
+      layers : layers,
+    })
+  }
+}
+
+fn read_v1(r : &mut Reader) -> Result<Document, DocumentError> {
+  let width = r.u32()?;
+  let height = r.u32()?;
+  let thresh_base = r.f32()?;
+  let thresh_t_var = r.f32()?;
+  let thresh_t_mult = r.f32()?;
+  let t = r.u32()?;
+  let seed = r.u64()?;
+
+  check_dimensions(width, height)?;
+
+  let layer = LayerDocument {
+    thresh_base : thresh_base,
+    thresh_t_var : thresh_t_var,
+    thresh_t_mult : thresh_t_mult,
+    wave : Wave::default(),
+    visible : true,
+    points : r.points(width, height, 1)?,
+  };
+
+  Ok(Document {
+    width : width,
+    height : height,
+    t : t,
+    seed : seed,
+    active_layer : 0,
+    ramp : ColorRamp::default(),
+    palette : Palette::default(),
+    outline_width : DEFAULT_OUTLINE_WIDTH,
+    wrap : false,
+    layers : vec![layer],
+  })
+}
+
+fn check_dimensions(width : u32, height : u32) -> Result<(), DocumentError> {
+  if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+    Err(DocumentError::BadDimensions(width, height))
+  }
+  else {
+    Ok(())
+  }
+}
+
+#[derive(Default)]
+struct Writer {
+  buf : Vec<u8>,
+}
+
+impl Writer {
+  fn bytes(&mut self, x : &[u8]) {
+    self.buf.extend_from_slice(x);
+  }
+
+  fn u8(&mut self, x : u8) {
+    self.bytes(&[x]);
+  }
+
+  fn u16(&mut self, x : u16) {
+    self.bytes(&x.to_le_bytes());
+  }
+
+  fn u32(&mut self, x : u32) {
+    self.bytes(&x.to_le_bytes());
+  }
+
+  fn u64(&mut self, x : u64) {
+    self.bytes(&x.to_le_bytes());
+  }
+
+  fn f32(&mut self, x : f32) {
+    self.bytes(&x.to_le_bytes());
+  }
+}
+
+struct Reader<'t> {
+  data : &'t [u8],
+}
+
+impl<'t> Reader<'t> {
+  fn bytes(&mut self, n : usize) -> Result<&'t [u8], DocumentError> {
+    if self.data.len() < n {
+      return Err(DocumentError::UnexpectedEof);
+    }
+
+    let (head, tail) = self.data.split_at(n);
+    self.data = tail;
+    Ok(head)
+  }
+
+  fn u8(&mut self) -> Result<u8, DocumentError> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, DocumentError> {
+    Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+  }
+
+  fn u32(&mut self) -> Result<u32, DocumentError> {
+    Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64, DocumentError> {
+    Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+  }
+
+  fn f32(&mut self) -> Result<f32, DocumentError> {
+    Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+  }
+
+  fn ramp(&mut self) -> Result<ColorRamp, DocumentError> {
+    let background = self.u8()?;
+    let outline = self.u8()?;
+    let ink_count = self.u8()?;
+    if ink_count == 0 {
+      return Err(DocumentError::NoInks);
+    }
+
+    Ok(ColorRamp {
+      inks : self.bytes(ink_count as usize)?.to_vec(),
+      outline : outline,
+      background : background,
+    })
+  }
+
+  fn palette(&mut self) -> Result<Palette, DocumentError> {
+    let name_len = self.u16()?;
+    let name = String::from_utf8_lossy(self.bytes(name_len as usize)?).into_owned();
+    let count = self.u16()?;
+    let colors = self.bytes(3 * count as usize)?
+      .chunks(3)
+      .map(|c| [c[0], c[1], c[2]])
+      .collect();
+    Palette::new(&name, colors).map_err(DocumentError::BadPalette)
+  }
+
+  fn wave(&mut self) -> Result<Wave, DocumentError> {
+    let direction = self.f32()?;
+    let frequency = self.f32()?;
+    let radial = self.u8()? != 0;
+    let centre_x = self.f32()?;
+    let centre_y = self.f32()?;
+    let waveform = self.u8()?;
+    Ok(Wave {
+      direction : direction,
+      frequency : frequency,
+      radial : radial,
+      centre_x : centre_x,
+      centre_y : centre_y,
+      waveform : Waveform::from_u8(waveform).ok_or(DocumentError::BadWaveform(waveform))?,
+    })
+  }
+
+  /// Channels a `version` document doesn't have get their defaults.
+  fn points(&mut self, width : u32, height : u32, version : u16) -> Result<Vec<PointData>, DocumentError> {
+    let count = width as usize * height as usize;
+    let point_size = match version {
+      1 | 2 => 8,
+      3..=6 => 16,
+      _ => 20,
+    };
+
+    // Check up front so a corrupt header can't make us allocate a huge buffer.
+    if self.data.len() / point_size < count {
+      return Err(DocumentError::UnexpectedEof);
+    }
+
+    let mut points = Vec::with_capacity(count);
+    for _i in 0..count {
+      let thresh_band = self.f32()?;
+      let color_band = self.f32()?;
+      let mut p = PointData::new(thresh_band, color_band);
+      if version >= 3 {
+        p.wobble_amp = self.f32()?;
+        p.wobble_phase = self.f32()?;
+      }
+      if version >= 7 {
+        p.line_weight = self.f32()?;
+      }
+      points.push(p);
+    }
+    Ok(points)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Saved by version 1, a 6x4 canvas with one stamp in the middle.
+  const V1 : &[u8] = include_bytes!("../tests/fixtures/v1.blob");
+
+  /// Saved by version 4, a 6x4 canvas with a hidden bottom layer and a top
+  /// layer with its own wave and a wobble stamp.
+  const V4 : &[u8] = include_bytes!("../tests/fixtures/v4.blob");
+
+  fn point(i : usize) -> PointData {
+    let k = i as f32 / 10.0;
+    PointData {
+      thresh_band : k,
+      color_band : 1.0 - k,
+      wobble_amp : 2.0 * k,
+      wobble_phase : k / 2.0,
+      line_weight : 3.0 - k,
+    }
+  }
+
+  /// Every field set away from its default.
+  fn document() -> Document {
+    let layer = |thresh_base, visible| LayerDocument {
+      thresh_base : thresh_base,
+      thresh_t_var : 0.2,
+      thresh_t_mult : 0.003,
+      wave : Wave {
+        direction : 0.5,
+        frequency : 3.0,
+        radial : true,
+        centre_x : 0.25,
+        centre_y : 0.75,
+        waveform : Waveform::Noise,
+      },
+      visible : visible,
+      points : (0..6).map(point).collect(),
+    };
+
+    Document {
+      width : 3,
+      height : 2,
+      t : 1000,
+      seed : 7,
+      active_layer : 1,
+      ramp : ColorRamp {
+        inks : vec![2, 4, 5],
+        outline : 1,
+        background : 0,
+      },
+      palette : Palette::new("Test", vec![[1, 2, 3]; 6]).unwrap(),
+      outline_width : 0.1,
+      wrap : true,
+      layers : vec![layer(0.3, false), layer(0.6, true)],
+    }
+  }
+
+  #[test]
+  fn round_trip() {
+    let bytes = document().write();
+    let doc = Document::read(&bytes).unwrap();
+    assert_eq!(doc.write(), bytes);
+
+    assert_eq!((doc.width, doc.height, doc.t, doc.seed, doc.active_layer), (3, 2, 1000, 7, 1));
+    assert_eq!(doc.ramp, document().ramp);
+    assert_eq!(doc.palette, document().palette);
+    assert_eq!(doc.outline_width, 0.1);
+    assert!(doc.wrap);
+    assert_eq!(doc.layers.len(), 2);
+    assert!(!doc.layers[0].visible);
+    assert_eq!(doc.layers[1].thresh_base, 0.6);
+    assert_eq!(doc.layers[1].wave.waveform, Waveform::Noise);
+    assert_eq!(doc.layers[1].points.len(), 6);
+    assert_eq!(doc.layers[1].points[4].line_weight, point(4).line_weight);
+  }
+
+  #[test]
+  fn reads_v1() {
+    let doc = Document::read(V1).unwrap();
+    assert_eq!((doc.width, doc.height, doc.t), (6, 4, 1234));
+    assert_eq!(doc.active_layer, 0);
+    assert_eq!(doc.ramp, ColorRamp::default());
+    assert_eq!(doc.palette, Palette::default());
+    assert_eq!(doc.outline_width, DEFAULT_OUTLINE_WIDTH);
+    assert!(!doc.wrap);
+
+    assert_eq!(doc.layers.len(), 1);
+    let layer = &doc.layers[0];
+    assert_eq!((layer.thresh_base, layer.thresh_t_var, layer.thresh_t_mult), (0.3, 0.2, 0.001));
+    assert_eq!(layer.wave.waveform, Waveform::Sine);
+    assert!(!layer.wave.radial);
+    assert!(layer.visible);
+    assert_eq!(layer.points.len(), 24);
+    assert_eq!(layer.points[0].thresh_band, 0.0);
+    // The middle of the stamp.
+    let p = layer.points[2 * 6 + 3];
+    assert_eq!(p.thresh_band, 1.0);
+    assert_eq!((p.wobble_amp, p.wobble_phase, p.line_weight), (1.0, 0.0, 1.0));
+  }
+
+  #[test]
+  fn reads_v4() {
+    let doc = Document::read(V4).unwrap();
+    assert_eq!((doc.width, doc.height, doc.t, doc.seed), (6, 4, 5678, 42));
+    assert_eq!(doc.active_layer, 0);
+    assert_eq!(doc.ramp, ColorRamp::default());
+    assert_eq!(doc.palette, Palette::default());
+    assert_eq!(doc.outline_width, DEFAULT_OUTLINE_WIDTH);
+    assert!(!doc.wrap);
+
+    assert_eq!(doc.layers.len(), 2);
+    assert!(!doc.layers[0].visible);
+    assert_eq!(doc.layers[0].points[2 * 6 + 3].thresh_band, 1.0);
+
+    let layer = &doc.layers[1];
+    assert!(layer.visible);
+    assert_eq!(layer.thresh_base, 0.3);
+    assert_eq!((layer.wave.direction, layer.wave.frequency), (1.5, 2.0));
+    assert!(layer.wave.radial);
+    assert_eq!((layer.wave.centre_x, layer.wave.centre_y), (0.25, 0.75));
+    assert_eq!(layer.wave.waveform, Waveform::Triangle);
+    let p = layer.points[2 * 6 + 3];
+    assert_eq!((p.wobble_amp, p.wobble_phase, p.line_weight), (0.5, 0.25, 1.0));
+  }
+
+  #[test]
+  fn rejects_bad_documents() {
+    let bytes = document().write();
+    assert_eq!(Document::read(&bytes[..bytes.len() - 1]).unwrap_err(), DocumentError::UnexpectedEof);
+
+    let mut newer = bytes.clone();
+    newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert_eq!(Document::read(&newer).unwrap_err(), DocumentError::UnsupportedVersion(FORMAT_VERSION + 1));
+
+    assert_eq!(Document::read(b"GIF89a").unwrap_err(), DocumentError::BadMagic);
+  }
+}
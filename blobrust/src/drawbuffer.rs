@@ -0,0 +1,137 @@
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+/// Largest canvas dimension we support.
+/// Fits a WideDrawPoint and keeps width * height within the u32 point indices.
+pub const MAX_DIMENSION : u32 = u16::MAX as u32;
+
+/// Largest canvas dimension that fits in a narrow DrawPoint.
+pub const MAX_NARROW_DIMENSION : u32 = u8::MAX as u32 + 1;
+
+/// A canvas dimension of 0 or over MAX_DIMENSION.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeError {
+  pub width : u32,
+  pub height : u32,
+}
+
+impl fmt::Display for SizeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid canvas dimensions {}x{}, each must be 1 to {}", self.width, self.height, MAX_DIMENSION)
+  }
+}
+
+impl std::error::Error for SizeError {}
+
+pub fn check_size(width : u32, height : u32) -> Result<(), SizeError> {
+  if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+    Err(SizeError {
+      width : width,
+      height : height,
+    })
+  }
+  else {
+    Ok(())
+  }
+}
+
+/// A point to be draw to the html canvas.
+/// Only used for canvases up to 256 x 256, see WideDrawPoint.
+#[wasm_bindgen]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct DrawPoint {
+  pub x : u8,
+  pub y : u8,
+  /// Palette index.
+  pub col: u8,
+}
+
+/// A point to be draw to the html canvas for canvases larger than 256 x 256.
+/// Laid out as [x, y, col] u16s so js can view it as a Uint16Array.
+#[wasm_bindgen]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct WideDrawPoint {
+  pub x : u16,
+  pub y : u16,
+  /// Palette index, widened to match x and y.
+  pub col: u16,
+}
+
+/// Points sampled this frame.
+/// The narrow layout is kept for small canvases to halve what the js side has to read.
+pub enum DrawBuffer {
+  Narrow(Vec<DrawPoint>),
+  Wide(Vec<WideDrawPoint>),
+}
+
+impl DrawBuffer {
+  pub fn new(len : usize, wide : bool) -> Self {
+    if wide {
+      DrawBuffer::Wide(vec![WideDrawPoint::default(); len])
+    }
+    else {
+      DrawBuffer::Narrow(vec![DrawPoint::default(); len])
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    match self {
+      DrawBuffer::Narrow(points) => points.len(),
+      DrawBuffer::Wide(points) => points.len(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn is_wide(&self) -> bool {
+    match self {
+      DrawBuffer::Narrow(_) => false,
+      DrawBuffer::Wide(_) => true,
+    }
+  }
+
+  /// Coordinates must fit the layout, the canvas picks a wide buffer
+  /// when either dimension is too large for a narrow one.
+  pub fn set(&mut self, i : usize, x : u32, y : u32, col : u8) {
+    match self {
+      DrawBuffer::Narrow(points) => {
+        debug_assert!(x < MAX_NARROW_DIMENSION && y < MAX_NARROW_DIMENSION);
+        points[i] = DrawPoint {
+          x : x as u8,
+          y : y as u8,
+          col : col,
+        }
+      },
+      DrawBuffer::Wide(points) => {
+        debug_assert!(x < MAX_DIMENSION && y < MAX_DIMENSION);
+        points[i] = WideDrawPoint {
+          x : x as u16,
+          y : y as u16,
+          col : col as u16,
+        }
+      },
+    }
+  }
+
+  pub fn get(&self, i : usize) -> (u32, u32, u8) {
+    match self {
+      DrawBuffer::Narrow(points) => {
+        let p = points[i];
+        (p.x as u32, p.y as u32, p.col)
+      },
+      DrawBuffer::Wide(points) => {
+        let p = points[i];
+        (p.x as u32, p.y as u32, p.col as u8)
+      },
+    }
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (u32, u32, u8)> + '_ {
+    (0..self.len()).map(move |i| self.get(i))
+  }
+}
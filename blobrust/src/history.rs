@@ -0,0 +1,484 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::pointdata::{PointData, PointDataStore, DirtyRect};
+use super::layer::Layer;
+use super::selection::Selection;
+
+pub const DEFAULT_UNDO_DEPTH : usize = 100;
+
+/// Steps are saved in square tiles of this many points a side, so a long stroke
+/// only keeps the tiles it passes through rather than its whole bounding box.
+const TILE_SIZE : u32 = 32;
+
+/// The state of a region of the canvas from before an edit.
+/// Only the tiles touched by the edit are kept, rather than the whole canvas.
+pub struct UndoStep {
+  layer : usize,
+  tiles : HashMap<(u32, u32), (DirtyRect, Vec<PointData>)>,
+}
+
+impl UndoStep {
+  fn new() -> Self {
+    UndoStep {
+      layer : 0,
+      tiles : HashMap::new(),
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.tiles.is_empty()
+  }
+
+  /// Put the saved data back onto its layer.
+  /// The step is left holding what was there, so restoring it again reverses this.
+  fn restore(&mut self, layers : &mut [Layer]) {
+    let store = &mut layers[self.layer].data;
+    for (rect, data) in self.tiles.values_mut() {
+      store.swap_rect(rect, data);
+    }
+  }
+
+  /// Number of points saved, across every tile.
+  #[cfg(test)]
+  fn point_count(&self) -> usize {
+    self.tiles.values().map(|(_, data)| data.len()).sum()
+  }
+}
+
+/// Every layer in full from before an edit that moves everything around or
+/// resizes the canvas, such as a rotation. The selection is kept too, since
+/// these can move it.
+pub struct CanvasStep {
+  width : u32,
+  height : u32,
+  layers : Vec<Vec<PointData>>,
+  selection : Option<Selection>,
+}
+
+impl CanvasStep {
+  fn new(layers : &[Layer], selection : Option<&Selection>) -> Self {
+    CanvasStep {
+      width : layers[0].data.get_width(),
+      height : layers[0].data.get_height(),
+      layers : layers.iter().map(|layer| layer.data.get_clone()).collect(),
+      selection : selection.cloned(),
+    }
+  }
+
+  /// As UndoStep::restore, the canvas may change size.
+  /// Layers added on top since the step was saved have nothing to go back to,
+  /// they come back empty at the saved size and are saved in turn.
+  fn restore(&mut self, layers : &mut [Layer], selection : &mut Option<Selection>) {
+    let (width, height) = (layers[0].data.get_width(), layers[0].data.get_height());
+    let len = self.width as usize * self.height as usize;
+    self.layers.resize_with(layers.len(), || vec![PointData::new(0.0, 0.0); len]);
+    for (layer, data) in layers.iter_mut().zip(self.layers.iter_mut()) {
+      let saved = std::mem::take(data);
+      *data = layer.data.get_clone();
+      layer.data.replace(saved, self.width, self.height);
+    }
+    self.width = width;
+    self.height = height;
+    std::mem::swap(&mut self.selection, selection);
+  }
+}
+
+enum Step {
+  Region(UndoStep),
+  Canvas(CanvasStep),
+}
+
+impl Step {
+  fn restore(&mut self, layers : &mut [Layer], selection : &mut Option<Selection>) {
+    match self {
+      Step::Region(step) => step.restore(layers),
+      Step::Canvas(step) => step.restore(layers, selection),
+    }
+  }
+}
+
+/// Undo history built from the regions each edit touches.
+///
+/// push_undo opens a new step, then before anything writes to the canvas it
+/// calls record with the rect it is about to change. The first time a tile is
+/// recorded within a step we save it, so the step ends up holding the canvas as
+/// it was before the edit over every tile touched.
+///
+/// Undoing a step swaps it with the canvas and moves it onto the redo stack,
+/// redoing swaps it back. The redo stack is dropped as soon as a new edit writes
+/// anything.
+///
+/// Each step belongs to a single layer, by index. Anything that removes or
+/// reorders layers must clear the history, adding one on top leaves the
+/// indices of the rest alone.
+///
+/// Edits to the whole canvas, which can change its size, are recorded with
+/// record_canvas instead and saved as a step of their own, holding every layer
+/// and the selection.
+pub struct UndoHistory {
+  depth : usize,
+  steps : VecDeque<Step>,
+  redo_steps : Vec<Step>,
+  pending : Option<UndoStep>,
+}
+
+impl UndoHistory {
+  pub fn new(depth : usize) -> Self {
+    UndoHistory {
+      depth : depth,
+      steps : VecDeque::new(),
+      redo_steps : Vec::new(),
+      pending : None,
+    }
+  }
+
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  pub fn set_depth(&mut self, depth : usize) {
+    self.depth = depth;
+    self.trim();
+  }
+
+  pub fn len(&self) -> usize {
+    self.steps.len() + self.pending.as_ref().map_or(0, |s| !s.is_empty() as usize)
+  }
+
+  pub fn redo_len(&self) -> usize {
+    self.redo_steps.len()
+  }
+
+  /// Start recording a new step, closing off the last.
+  pub fn push(&mut self) {
+    self.finish_pending();
+    self.pending = Some(UndoStep::new());
+  }
+
+  /// Throw away the open step, for an edit that was taken back before it
+  /// finished. Later records go into a new step.
+  pub fn discard(&mut self) {
+    self.pending = Some(UndoStep::new());
+  }
+
+  /// Drop every step, for when layer indices change under them.
+  pub fn clear(&mut self) {
+    self.steps.clear();
+    self.redo_steps.clear();
+    self.pending = None;
+  }
+
+  /// Save the current contents of `rect` in `layer` into the open step, if there
+  /// is one. Must be called before the rect is written to.
+  pub fn record(&mut self, layer : usize, store : &PointDataStore, rect : &DirtyRect) {
+    // Writing to another layer mid step, say after switching the active layer,
+    // carries on in a new step.
+    match self.pending.as_ref() {
+      Some(step) if step.layer != layer && !step.is_empty() => self.push(),
+      _ => {}
+    }
+
+    let step = match self.pending.as_mut() {
+      Some(step) => step,
+      None => return,
+    };
+    // A new edit, whatever was undone can't be redone on top of it.
+    self.redo_steps.clear();
+    step.layer = layer;
+
+    let (width, height) = (store.get_width(), store.get_height());
+    let rect = clamp_rect(rect, width, height);
+    if rect.is_empty() {
+      return;
+    }
+
+    // Nothing in a tile has been written to since the step opened until it is
+    // first recorded, so the store still holds its original values.
+    for ty in rect.min_y / TILE_SIZE..=(rect.max_y - 1) / TILE_SIZE {
+      for tx in rect.min_x / TILE_SIZE..=(rect.max_x - 1) / TILE_SIZE {
+        step.tiles.entry((tx, ty)).or_insert_with(|| {
+          let tile = clamp_rect(&DirtyRect {
+            min_x : tx * TILE_SIZE,
+            min_y : ty * TILE_SIZE,
+            max_x : (tx + 1) * TILE_SIZE,
+            max_y : (ty + 1) * TILE_SIZE,
+          }, width, height);
+          (tile, store.copy_rect(&tile))
+        });
+      }
+    }
+  }
+
+  /// Save every layer and the selection as their own step before an edit to the
+  /// whole canvas. Later records go into a new step.
+  pub fn record_canvas(&mut self, layers : &[Layer], selection : Option<&Selection>) {
+    self.redo_steps.clear();
+    self.finish_pending();
+    self.steps.push_back(Step::Canvas(CanvasStep::new(layers, selection)));
+    self.trim();
+    self.pending = Some(UndoStep::new());
+  }
+
+  /// Undo the most recent step, closing the open one first.
+  pub fn undo(&mut self, layers : &mut [Layer], selection : &mut Option<Selection>) -> bool {
+    self.finish_pending();
+    // Anything drawn after this, say the rest of a stroke, goes into a new step.
+    self.pending = Some(UndoStep::new());
+    match self.steps.pop_back() {
+      Some(mut step) => {
+        step.restore(layers, selection);
+        self.push_redo(step);
+        true
+      },
+      _ => false,
+    }
+  }
+
+  /// Reapply the most recently undone step.
+  pub fn redo(&mut self, layers : &mut [Layer], selection : &mut Option<Selection>) -> bool {
+    self.finish_pending();
+    // Anything drawn after this, say the rest of a stroke, goes into a new step.
+    self.pending = Some(UndoStep::new());
+    match self.redo_steps.pop() {
+      Some(mut step) => {
+        step.restore(layers, selection);
+        self.steps.push_back(step);
+        self.trim();
+        true
+      },
+      _ => false,
+    }
+  }
+
+  fn push_redo(&mut self, step : Step) {
+    self.redo_steps.push(step);
+    if self.redo_steps.len() > self.depth {
+      let _ = self.redo_steps.remove(0);
+    }
+  }
+
+  fn finish_pending(&mut self) {
+    if let Some(step) = self.pending.take() {
+      // Nothing was written, no point keeping an undo that does nothing.
+      if !step.is_empty() {
+        self.steps.push_back(Step::Region(step));
+        self.trim();
+      }
+    }
+  }
+
+  fn trim(&mut self) {
+    while self.steps.len() > self.depth {
+      let _ = self.steps.pop_front();
+    }
+  }
+}
+
+fn clamp_rect(rect : &DirtyRect, width : u32, height : u32) -> DirtyRect {
+  DirtyRect {
+    min_x : rect.min_x.min(width),
+    min_y : rect.min_y.min(height),
+    max_x : rect.max_x.min(width),
+    max_y : rect.max_y.min(height),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn layer(width : u32, height : u32, thresh_band : f32) -> Layer {
+    let data = vec![PointData::new(thresh_band, 0.0); (width * height) as usize];
+    Layer::new(PointDataStore::new(data, width, height))
+  }
+
+  fn rect(min_x : u32, min_y : u32, max_x : u32, max_y : u32) -> DirtyRect {
+    DirtyRect {
+      min_x : min_x,
+      min_y : min_y,
+      max_x : max_x,
+      max_y : max_y,
+    }
+  }
+
+  /// Record then write `value` over `rect`, as an edit would.
+  fn paint(history : &mut UndoHistory, layers : &mut [Layer], index : usize, rect : DirtyRect, value : f32) {
+    history.record(index, &layers[index].data, &rect);
+    for y in rect.min_y..rect.max_y {
+      for x in rect.min_x..rect.max_x {
+        layers[index].data.get_mut(x, y).thresh_band = value;
+      }
+    }
+  }
+
+  fn thresh(layer : &Layer) -> Vec<f32> {
+    layer.data.get_clone().iter().map(|p| p.thresh_band).collect()
+  }
+
+  #[test]
+  fn record_keeps_first_values() {
+    let mut layers = vec![layer(40, 40, 0.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.push();
+    paint(&mut history, &mut layers, 0, rect(0, 0, 2, 2), 1.0);
+    // Overlaps the first rect, which must still come back as 0 rather than 1.
+    paint(&mut history, &mut layers, 0, rect(1, 1, 3, 3), 2.0);
+    // Crosses into the next tiles along.
+    paint(&mut history, &mut layers, 0, rect(30, 30, 36, 36), 3.0);
+    assert_eq!(history.len(), 1);
+
+    assert!(history.undo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[0]), vec![0.0; 1600]);
+  }
+
+  #[test]
+  fn depth_drops_oldest_steps() {
+    let mut layers = vec![layer(4, 4, 0.0)];
+    let mut history = UndoHistory::new(2);
+    for i in 0..4 {
+      history.push();
+      paint(&mut history, &mut layers, 0, rect(i, 0, i + 1, 1), 1.0);
+    }
+    history.push();
+    assert_eq!(history.len(), 2);
+
+    while history.undo(&mut layers, &mut None) {}
+    assert_eq!(thresh(&layers[0])[..4], [1.0, 1.0, 0.0, 0.0]);
+  }
+
+  #[test]
+  fn diagonal_stroke_keeps_tiles_not_bounding_box() {
+    let size = 1024;
+    let mut layers = vec![layer(size, size, 0.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.push();
+    for i in 0..size - 8 {
+      paint(&mut history, &mut layers, 0, rect(i, i, i + 8, i + 8), 1.0);
+    }
+
+    // The stroke passes through the diagonal tiles and clips the ones beside
+    // them, a bounding box would have saved the whole canvas.
+    let tiles = (size / TILE_SIZE) as usize * 3;
+    let step = history.pending.as_ref().unwrap();
+    assert!(step.point_count() <= tiles * (TILE_SIZE * TILE_SIZE) as usize);
+
+    assert!(history.undo(&mut layers, &mut None));
+    assert!(thresh(&layers[0]).iter().all(|&t| t == 0.0));
+  }
+
+  #[test]
+  fn undo_and_redo_swap_steps() {
+    let mut layers = vec![layer(4, 4, 0.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.push();
+    paint(&mut history, &mut layers, 0, rect(0, 0, 2, 2), 1.0);
+    let first = thresh(&layers[0]);
+    history.push();
+    paint(&mut history, &mut layers, 0, rect(1, 1, 4, 4), 2.0);
+    let second = thresh(&layers[0]);
+
+    assert!(history.undo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[0]), first);
+    assert!(history.undo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[0]), vec![0.0; 16]);
+    assert!(!history.undo(&mut layers, &mut None));
+
+    assert!(history.redo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[0]), first);
+    assert!(history.redo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[0]), second);
+    assert!(!history.redo(&mut layers, &mut None));
+  }
+
+  #[test]
+  fn new_edit_drops_redo() {
+    let mut layers = vec![layer(4, 4, 0.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.push();
+    paint(&mut history, &mut layers, 0, rect(0, 0, 2, 2), 1.0);
+    assert!(history.undo(&mut layers, &mut None));
+    assert_eq!(history.redo_len(), 1);
+
+    history.push();
+    paint(&mut history, &mut layers, 0, rect(2, 2, 4, 4), 2.0);
+    assert_eq!(history.redo_len(), 0);
+  }
+
+  #[test]
+  fn undo_mid_step_keeps_recording() {
+    let mut layers = vec![layer(4, 4, 0.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.push();
+    paint(&mut history, &mut layers, 0, rect(0, 0, 2, 2), 1.0);
+    assert!(history.undo(&mut layers, &mut None));
+
+    // The rest of the stroke lands in a step of its own.
+    paint(&mut history, &mut layers, 0, rect(2, 2, 4, 4), 2.0);
+    assert_eq!(history.len(), 1);
+    assert!(history.undo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[0]), vec![0.0; 16]);
+    assert!(history.redo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[0])[15], 2.0);
+    assert!(!history.redo(&mut layers, &mut None));
+  }
+
+  #[test]
+  fn switching_layers_starts_a_new_step() {
+    let mut layers = vec![layer(4, 4, 0.0), layer(4, 4, 0.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.push();
+    paint(&mut history, &mut layers, 0, rect(0, 0, 2, 2), 1.0);
+    paint(&mut history, &mut layers, 1, rect(0, 0, 2, 2), 1.0);
+    assert_eq!(history.len(), 2);
+
+    assert!(history.undo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[1]), vec![0.0; 16]);
+    assert_ne!(thresh(&layers[0]), vec![0.0; 16]);
+  }
+
+  #[test]
+  fn canvas_step_restores_size() {
+    let mut layers = vec![layer(4, 2, 1.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.record_canvas(&layers, None);
+    layers[0].data.replace(vec![PointData::new(2.0, 0.0); 3], 1, 3);
+
+    assert!(history.undo(&mut layers, &mut None));
+    assert_eq!((layers[0].data.get_width(), layers[0].data.get_height()), (4, 2));
+    assert_eq!(thresh(&layers[0]), vec![1.0; 8]);
+    assert!(history.redo(&mut layers, &mut None));
+    assert_eq!((layers[0].data.get_width(), layers[0].data.get_height()), (1, 3));
+    assert_eq!(thresh(&layers[0]), vec![2.0; 3]);
+  }
+
+  #[test]
+  fn canvas_step_resizes_layers_added_since() {
+    let mut layers = vec![layer(4, 2, 1.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    history.record_canvas(&layers, None);
+    layers[0].data.replace(vec![PointData::new(2.0, 0.0); 3], 1, 3);
+    layers.push(layer(1, 3, 3.0));
+
+    assert!(history.undo(&mut layers, &mut None));
+    for layer in &layers {
+      assert_eq!((layer.data.get_width(), layer.data.get_height()), (4, 2));
+    }
+    assert!(history.redo(&mut layers, &mut None));
+    assert_eq!(thresh(&layers[1]), vec![3.0; 3]);
+  }
+
+  #[test]
+  fn canvas_step_restores_selection() {
+    let mut layers = vec![layer(4, 2, 1.0)];
+    let mut history = UndoHistory::new(DEFAULT_UNDO_DEPTH);
+    let before = Selection::from_fn(4, 2, |x, _| x == 0);
+    let after = Selection::from_fn(4, 2, |x, _| x == 3);
+    history.record_canvas(&layers, Some(&before));
+    let mut selection = Some(after.clone());
+
+    assert!(history.undo(&mut layers, &mut selection));
+    assert_eq!(selection.as_ref(), Some(&before));
+    assert!(history.redo(&mut layers, &mut selection));
+    assert_eq!(selection.as_ref(), Some(&after));
+  }
+}
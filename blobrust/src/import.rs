@@ -0,0 +1,306 @@
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+use super::pointdata::{PointData, PointDataStore};
+use super::drawbuffer::check_size;
+use super::utils::clamp_unit;
+
+/// What sets color_band for an imported image.
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ColorSource {
+  /// Hue around the colour wheel, greys come out as 0.
+  #[default]
+  Hue,
+  Red,
+  Green,
+  Blue,
+  Luminance,
+}
+
+/// How an image turns into points.
+///
+/// Luminance, blurred then stretched between the black and white points,
+/// becomes thresh_band. Transparent pixels are background whatever their colour.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct ImportSettings {
+  pub color_source : ColorSource,
+  /// Box blur radius in pixels, softens edges so they wobble rather than alias.
+  pub blur : u32,
+  /// Luminance, 0 to 1, at or below which thresh_band is 0.
+  pub black_point : f32,
+  /// Luminance at or above which thresh_band is 1.
+  pub white_point : f32,
+  /// Dark areas become blobs, for ink on paper.
+  pub invert : bool,
+}
+
+impl Default for ImportSettings {
+  /// Dark lines on a light background, slightly softened.
+  fn default() -> Self {
+    ImportSettings {
+      color_source : ColorSource::Hue,
+      blur : 1,
+      black_point : 0.0,
+      white_point : 1.0,
+      invert : true,
+    }
+  }
+}
+
+#[wasm_bindgen]
+impl ImportSettings {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+  BadDimensions(u32, u32),
+  /// Expected and actual buffer lengths.
+  BadLength(usize, usize),
+  Png(String),
+}
+
+impl fmt::Display for ImportError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ImportError::BadDimensions(w, h) => write!(f, "invalid image dimensions {}x{}", w, h),
+      ImportError::BadLength(expected, got) => write!(f, "image buffer is {} bytes, expected {}", got, expected),
+      ImportError::Png(e) => write!(f, "can't read png: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Points from rgba pixels, 4 bytes each in row order.
+pub fn import_rgba(data : &[u8], width : u32, height : u32, settings : &ImportSettings) -> Result<PointDataStore, ImportError> {
+  check_buffer(data, width, height, 4)?;
+  let pixels = data.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]);
+  Ok(import_pixels(pixels, width, height, settings))
+}
+
+/// Points from greyscale pixels, a byte each in row order.
+pub fn import_grey(data : &[u8], width : u32, height : u32, settings : &ImportSettings) -> Result<PointDataStore, ImportError> {
+  check_buffer(data, width, height, 1)?;
+  let pixels = data.iter().map(|&l| [l, l, l, 0xFF]);
+  Ok(import_pixels(pixels, width, height, settings))
+}
+
+/// Points from a png file, in any of its colour types.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn import_png(bytes : &[u8], settings : &ImportSettings) -> Result<PointDataStore, ImportError> {
+  let png_err = |e : png::DecodingError| ImportError::Png(e.to_string());
+  let mut decoder = png::Decoder::new(bytes);
+  // Palettes and low bit depths expanded, 16 bit cut down, to 8 bits a channel.
+  decoder.set_transformations(png::Transformations::normalize_to_color8());
+  let mut reader = decoder.read_info().map_err(png_err)?;
+  let mut buf = vec![0; reader.output_buffer_size()];
+  let info = reader.next_frame(&mut buf).map_err(png_err)?;
+  let buf = &buf[..info.buffer_size()];
+  let (width, height) = (info.width, info.height);
+
+  match info.color_type {
+    png::ColorType::Rgba => import_rgba(buf, width, height, settings),
+    png::ColorType::Grayscale => import_grey(buf, width, height, settings),
+    png::ColorType::Rgb => {
+      let rgba : Vec<u8> = buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect();
+      import_rgba(&rgba, width, height, settings)
+    },
+    png::ColorType::GrayscaleAlpha => {
+      let rgba : Vec<u8> = buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect();
+      import_rgba(&rgba, width, height, settings)
+    },
+    png::ColorType::Indexed => Err(ImportError::Png("palette wasn't expanded".to_owned())),
+  }
+}
+
+fn check_buffer(data : &[u8], width : u32, height : u32, channels : usize) -> Result<(), ImportError> {
+  check_size(width, height).map_err(|_| ImportError::BadDimensions(width, height))?;
+
+  let expected = width as usize * height as usize * channels;
+  if data.len() != expected {
+    return Err(ImportError::BadLength(expected, data.len()));
+  }
+
+  Ok(())
+}
+
+fn import_pixels<I : Iterator<Item = [u8; 4]>>(pixels : I, width : u32, height : u32, settings : &ImportSettings) -> PointDataStore {
+  let mut ink = Vec::with_capacity(width as usize * height as usize);
+  let mut colors = Vec::with_capacity(width as usize * height as usize);
+  for [r, g, b, a] in pixels {
+    let (r, g, b, a) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0);
+    let lum = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    ink.push(if settings.invert { 1.0 - lum } else { lum } * a);
+    colors.push(match settings.color_source {
+      ColorSource::Hue => hue(r, g, b),
+      ColorSource::Red => r,
+      ColorSource::Green => g,
+      ColorSource::Blue => b,
+      ColorSource::Luminance => lum,
+    });
+  }
+
+  let ink = box_blur(&ink, width as usize, height as usize, settings.blur as usize);
+  let range = (settings.white_point - settings.black_point).max(f32::EPSILON);
+  let data = ink.iter().zip(colors)
+    .map(|(&t, col)| PointData::new(clamp_unit((t - settings.black_point) / range), col))
+    .collect();
+
+  PointDataStore::new(data, width, height)
+}
+
+/// Hue in [0, 1), red at 0.
+fn hue(r : f32, g : f32, b : f32) -> f32 {
+  let max = r.max(g).max(b);
+  let min = r.min(g).min(b);
+  let chroma = max - min;
+  if chroma == 0.0 {
+    return 0.0;
+  }
+
+  let sector = if max == r {
+    ((g - b) / chroma).rem_euclid(6.0)
+  }
+  else if max == g {
+    (b - r) / chroma + 2.0
+  }
+  else {
+    (r - g) / chroma + 4.0
+  };
+  sector / 6.0
+}
+
+/// Average over a (2 * radius + 1) square, shrinking to fit at the edges.
+fn box_blur(values : &[f32], width : usize, height : usize, radius : usize) -> Vec<f32> {
+  if radius == 0 {
+    return values.to_vec();
+  }
+
+  // Separable, across each row then down each column.
+  let pass = |src : &[f32], len : usize, count : usize, index : &dyn Fn(usize, usize) -> usize| {
+    let mut out = vec![0.0; src.len()];
+    for line in 0..count {
+      for i in 0..len {
+        let lo = i.saturating_sub(radius);
+        let hi = (i + radius).min(len - 1);
+        let sum : f32 = (lo..=hi).map(|j| src[index(line, j)]).sum();
+        out[index(line, i)] = sum / (hi - lo + 1) as f32;
+      }
+    }
+    out
+  };
+
+  let rows = pass(values, width, height, &|y, x| y * width + x);
+  pass(&rows, height, width, &|x, y| y * width + x)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drawbuffer::MAX_DIMENSION;
+
+  fn plain() -> ImportSettings {
+    ImportSettings {
+      blur : 0,
+      invert : false,
+      ..ImportSettings::default()
+    }
+  }
+
+  fn thresh(store : &PointDataStore) -> Vec<f32> {
+    store.get_clone().iter().map(|p| p.thresh_band).collect()
+  }
+
+  #[test]
+  fn reads_rgba() {
+    let data = [
+      0xFF, 0x00, 0x00, 0xFF,
+      0x00, 0xFF, 0x00, 0xFF,
+      0xFF, 0xFF, 0xFF, 0xFF,
+      0x00, 0x00, 0x00, 0xFF,
+    ];
+    let store = import_rgba(&data, 2, 2, &plain()).unwrap();
+    assert_eq!((store.get_width(), store.get_height()), (2, 2));
+    let points = store.get_clone();
+    assert!((points[0].color_band - 0.0).abs() < 1e-6);
+    assert!((points[1].color_band - 1.0 / 3.0).abs() < 1e-6);
+    assert_eq!(thresh(&store)[2..], [1.0, 0.0]);
+  }
+
+  #[test]
+  fn inverts_and_stretches_grey() {
+    let settings = ImportSettings {
+      black_point : 0.25,
+      white_point : 0.75,
+      ..plain()
+    };
+    let store = import_grey(&[0x00, 0x80, 0xFF, 0x20], 2, 2, &settings).unwrap();
+    let t = thresh(&store);
+    assert_eq!((t[0], t[2], t[3]), (0.0, 1.0, 0.0));
+    assert!((t[1] - 0.5).abs() < 0.01);
+
+    let inverted = ImportSettings {
+      invert : true,
+      ..plain()
+    };
+    let store = import_grey(&[0x00, 0xFF], 2, 1, &inverted).unwrap();
+    assert_eq!(thresh(&store), vec![1.0, 0.0]);
+  }
+
+  #[test]
+  fn transparent_is_background() {
+    let data = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF];
+    let settings = ImportSettings {
+      invert : true,
+      ..plain()
+    };
+    let store = import_rgba(&data, 2, 1, &settings).unwrap();
+    assert_eq!(thresh(&store), vec![0.0, 1.0]);
+  }
+
+  #[test]
+  fn blur_spreads_edges() {
+    let settings = ImportSettings {
+      blur : 1,
+      ..plain()
+    };
+    let store = import_grey(&[0x00, 0x00, 0xFF, 0xFF], 4, 1, &settings).unwrap();
+    let t = thresh(&store);
+    assert_eq!(t[0], 0.0);
+    assert!(t[1] > 0.0 && t[1] < t[2] && t[2] < 1.0);
+  }
+
+  #[test]
+  fn rejects_bad_sizes() {
+    assert_eq!(import_grey(&[], 0, 1, &plain()).err(), Some(ImportError::BadDimensions(0, 1)));
+    assert_eq!(
+      import_grey(&[0], MAX_DIMENSION + 1, 1, &plain()).err(),
+      Some(ImportError::BadDimensions(MAX_DIMENSION + 1, 1)));
+    assert_eq!(import_rgba(&[0; 12], 2, 2, &plain()).err(), Some(ImportError::BadLength(16, 12)));
+  }
+
+  #[test]
+  #[cfg(not(target_arch = "wasm32"))]
+  fn reads_png() {
+    let mut bytes = Vec::new();
+    {
+      let mut encoder = png::Encoder::new(&mut bytes, 3, 1);
+      encoder.set_color(png::ColorType::GrayscaleAlpha);
+      encoder.set_depth(png::BitDepth::Eight);
+      let mut writer = encoder.write_header().unwrap();
+      writer.write_image_data(&[0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x00]).unwrap();
+    }
+
+    let store = import_png(&bytes, &plain()).unwrap();
+    assert_eq!((store.get_width(), store.get_height()), (3, 1));
+    assert_eq!(thresh(&store), vec![1.0, 0.0, 0.0]);
+    assert!(matches!(import_png(&[0; 8], &plain()), Err(ImportError::Png(_))));
+  }
+}
@@ -0,0 +1,59 @@
+use std::f32::consts::TAU;
+
+use super::pointdata::{PointData, PointDataStore};
+use super::ramp::ColorRamp;
+use super::wave::Wave;
+
+/// The canvas every layer is sampled on.
+pub struct RenderContext<'r> {
+  pub width : u32,
+  pub height : u32,
+  /// Waves tile across the edges, see BlobCanvas::set_wrap.
+  pub wrap : bool,
+  pub ramp : &'r ColorRamp,
+  pub outline_width : f32,
+}
+
+/// One field of points with its own threshold wave.
+///
+/// Layers are composited top down, wherever a layer samples to background the
+/// layer below shows through.
+pub struct Layer {
+  pub data : PointDataStore,
+  pub thresh_base : f32,
+  pub thresh_t_var : f32,
+  pub thresh_t_mult : f32,
+  pub wave : Wave,
+  pub visible : bool,
+}
+
+impl Layer {
+  pub fn new(data : PointDataStore) -> Self {
+    Layer {
+      data : data,
+      thresh_base : 0.4,
+      thresh_t_var : 0.095,
+      thresh_t_mult : TAU / 1_000_000.0,
+      wave : Wave::default(),
+      visible : true,
+    }
+  }
+
+  /// Threshold at point `p`, at (x, y) on the canvas.
+  /// `phase` is the fraction of the way through the cycle, see phase.
+  pub fn thresh_at(&self, phase : f64, x : u32, y : u32, ctx : &RenderContext, p : &PointData) -> f32 {
+    let angle = self.wave.angle(phase, x, y, ctx.width, ctx.height, ctx.wrap) + TAU * p.wobble_phase;
+    self.thresh_base + self.thresh_t_var * p.wobble_amp * self.wave.shape(angle)
+  }
+
+  /// How far through its cycle the wave is at time `t`, in [0, 1).
+  pub fn phase(&self, t : u32) -> f64 {
+    let cycles = t as f64 * self.thresh_t_mult as f64 / std::f64::consts::TAU;
+    cycles - cycles.floor()
+  }
+
+  /// Microseconds per cycle, infinite when the wave isn't animated.
+  pub fn cycle_length_us(&self) -> f64 {
+    std::f64::consts::TAU / (self.thresh_t_mult as f64).abs()
+  }
+}
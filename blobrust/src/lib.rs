@@ -1,27 +1,54 @@
-/// Dan Slocombe 2020
-/// https://danslocom.be
+//! Dan Slocombe 2020
+//! https://danslocom.be
 
 extern crate rand_core;
 extern crate rand_xorshift;
+#[cfg(target_arch = "wasm32")]
 extern crate wee_alloc;
 
 mod utils;
 mod pointdata;
 mod brush;
-
-use std::f32::consts::TAU;
-use std::collections::VecDeque;
+mod document;
+mod drawbuffer;
+mod capture;
+mod history;
+mod stroke;
+mod layer;
+mod wave;
+mod ramp;
+mod palette;
+mod symmetry;
+mod selection;
+mod clipboard;
+mod import;
 
 use wasm_bindgen::prelude::*;
-use rand_core::{SeedableRng, RngCore};
+use rand_core::{RngCore};
 use rand_xorshift::{XorShiftRng};
 
-use utils::{rand_unit};
+use utils::{clamp_unit, rand_unit, stream_rng, RngStream};
 use pointdata::{PointData, PointDataStore, DirtyRect};
-use brush::{Brush};
+pub use brush::{Brush, BrushType, Dynamics, StampInput};
+pub use document::DocumentError;
+pub use wave::Waveform;
+pub use palette::{Palette, PaletteError, MAX_PALETTE_SIZE};
+pub use symmetry::SymmetryMode;
+pub use selection::SelectionOp;
+pub use import::{ColorSource, ImportSettings, ImportError};
+pub use drawbuffer::{DrawPoint, WideDrawPoint, DrawBuffer, SizeError, MAX_DIMENSION, MAX_NARROW_DIMENSION};
+use drawbuffer::check_size;
+use document::{Document, LayerDocument};
+use history::{UndoHistory, DEFAULT_UNDO_DEPTH};
+use stroke::{Stroke, STROKE_SPACING};
+use layer::{Layer, RenderContext};
+use ramp::ColorRamp;
+use symmetry::{Symmetry, IDENTITY};
+use selection::Selection;
+use clipboard::{Clip, FloatingPaste};
 
-const MAX_UNDOS : usize = 8;
 
+#[cfg(target_arch = "wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
@@ -33,109 +60,232 @@ extern {
 }
 */
 
+/// Palette indices of the original four colour layout, the default ColorRamp.
 #[wasm_bindgen]
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub enum Color {
+  #[default]
   X,
   Y,
   Z,
   THRESH,
 }
 
-impl Default for Color {
-  fn default() -> Self {
-    Color::X
-  }
-}
+/// Colours in the default layout.
+pub const COLOR_COUNT : usize = 4;
 
-/// A point to be draw to the html canvas.
-/// If we ever want to have a canvas larger than 256 x 256
-/// this needs to be increased.
+/// How close to the threshold a point has to be to draw as outline.
+pub const DEFAULT_OUTLINE_WIDTH : f32 = 0.05;
+
+/// Which bands BlobCanvas::fill sets.
 #[wasm_bindgen]
-#[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
-pub struct DrawPoint {
-  pub x : u8, 
-  pub y : u8,
-  pub col: Color,
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum FillBands {
+  #[default]
+  Both,
+  Thresh,
+  Color,
 }
 
 #[wasm_bindgen]
 pub struct BlobCanvas {
   width : u32,
   height : u32,
-  thresh_base : f32,
-  thresh_t_var : f32,
-  thresh_t_mult : f32,
-  data : PointDataStore,
-  undo_stack : VecDeque<Vec<PointData>>,
-  draw_buffer: Vec<DrawPoint>,
+  // Bottom first, never empty.
+  layers : Vec<Layer>,
+  active_layer : usize,
+  ramp : ColorRamp,
+  palette : Palette,
+  outline_width : f32,
+  history : UndoHistory,
+  stroke : Option<Stroke>,
+  symmetry : Symmetry,
+  /// Points edits may touch, None for all of them.
+  selection : Option<Selection>,
+  clipboard : Option<Clip>,
+  /// Pasted but not yet committed, see FloatingPaste.
+  paste : Option<FloatingPaste>,
+  /// Edges join up with the opposite edge, for tileable textures.
+  wrap : bool,
+  draw_buffer: DrawBuffer,
   t : u32,
-  rng: XorShiftRng,
+  seed : u64,
+  // Kept separate so the noise for a cleared canvas, dithering and which points
+  // get redrawn each frame don't depend on how often the others were used.
+  noise_rng : XorShiftRng,
+  dither_rng : XorShiftRng,
+  position_rng : XorShiftRng,
 }
 
-static RAND_SEED : [u8; 16] = [0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15];
+const RAND_SEED : u64 = 0x0f0e0d0c0b0a0908;
 
 #[wasm_bindgen]
 impl BlobCanvas {
-  pub fn new(width : u32, height: u32) -> Self {
-    let mut rng = XorShiftRng::from_seed(RAND_SEED);
-    let canvas_data = empty_canvas_data(width, height, &mut rng);
+  /// Canvases larger than 256 in either dimension get a wide draw buffer.
+  /// Throws if either dimension is 0 or over MAX_DIMENSION.
+  #[wasm_bindgen(js_name = new)]
+  pub fn new_js(width : u32, height: u32) -> Result<BlobCanvas, JsValue> {
+    Self::new(width, height).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+
+  /// A canvas whose noise, dithering and draw buffer positions all derive from
+  /// `seed`, so the same seed always produces the same frames.
+  #[wasm_bindgen(js_name = new_with_seed)]
+  pub fn new_with_seed_js(width : u32, height: u32, seed : u64) -> Result<BlobCanvas, JsValue> {
+    Self::new_with_seed(width, height, seed).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+
+  /// Always use a wide draw buffer, regardless of size.
+  #[wasm_bindgen(js_name = new_wide)]
+  pub fn new_wide_js(width : u32, height: u32) -> Result<BlobCanvas, JsValue> {
+    Self::new_wide(width, height).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+
+  pub fn get_seed(&self) -> u64 {
+    self.seed
+  }
+
+  /// Restart the dithering and draw buffer position streams from the seed,
+  /// so rendering from here is reproducible regardless of what came before.
+  pub fn reset_rngs(&mut self) {
+    self.dither_rng = stream_rng(self.seed, RngStream::Dither);
+    self.position_rng = stream_rng(self.seed, RngStream::Position);
+  }
+
+  /// Save the canvas, see document.rs for the format.
+  pub fn serialize(&self) -> Vec<u8> {
+    let layers = self.layers.iter()
+      .map(|layer| LayerDocument {
+        thresh_base : layer.thresh_base,
+        thresh_t_var : layer.thresh_t_var,
+        thresh_t_mult : layer.thresh_t_mult,
+        wave : layer.wave,
+        visible : layer.visible,
+        points : layer.data.get_clone(),
+      })
+      .collect();
+
+    Document {
+      width : self.width,
+      height : self.height,
+      t : self.t,
+      seed : self.seed,
+      active_layer : self.active_layer as u32,
+      ramp : self.ramp.clone(),
+      palette : self.palette.clone(),
+      outline_width : self.outline_width,
+      wrap : self.wrap,
+      layers : layers,
+    }.write()
+  }
+
+  #[wasm_bindgen(js_name = deserialize)]
+  pub fn deserialize_js(data : &[u8]) -> Result<BlobCanvas, JsValue> {
+    Self::deserialize(data).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+
+  /// A canvas traced from rgba pixels, such as canvas ImageData, see ImportSettings.
+  #[wasm_bindgen(js_name = from_rgba)]
+  pub fn from_rgba_js(data : &[u8], width : u32, height : u32, settings : &ImportSettings) -> Result<BlobCanvas, JsValue> {
+    Self::from_rgba(data, width, height, settings).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+
+  /// As from_rgba with a byte per pixel.
+  #[wasm_bindgen(js_name = from_grey)]
+  pub fn from_grey_js(data : &[u8], width : u32, height : u32, settings : &ImportSettings) -> Result<BlobCanvas, JsValue> {
+    Self::from_grey(data, width, height, settings).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
 
-    let pixels_updated_per_frame : usize = ((width as f32) * (height as f32) * 0.05) as usize;
-    let mut draw_buffer = Vec::with_capacity(pixels_updated_per_frame);
-    for _i in 0..pixels_updated_per_frame {
-      draw_buffer.push(DrawPoint::default());
+  pub fn tick(&mut self, microseconds: u32) {
+    self.t = self.t.wrapping_add(microseconds);
+    for layer in self.layers.iter_mut() {
+      layer.data.flush();
     }
+  }
 
-    BlobCanvas {
-      width : width,
-      height : height,
-      thresh_base: 0.4,
-      thresh_t_var: 0.095,
-      thresh_t_mult: TAU / 1_000_000.0,
-      data : canvas_data,
-      undo_stack : VecDeque::with_capacity(MAX_UNDOS+1),
-      draw_buffer: draw_buffer,
-      t : 0,
-      rng: rng,
+  /// Palette index of the topmost visible layer that isn't background at this point.
+  pub fn sample_pixel(&mut self, x : u32, y : u32) -> u8 {
+    let phases = self.current_phases();
+    self.sample_pixel_at(&phases, x, y)
+  }
+
+  /// Rasterize the whole canvas into `out` as width * height rgba pixels
+  /// in the canvas palette.
+  /// Without `dither` ink colours are picked by rounding color_band rather than
+  /// stochastically, so the result is stable from frame to frame.
+  pub fn render_rgba(&mut self, out : &mut [u8], dither : bool) {
+    let phases = self.current_phases();
+    self.render_frame_rgba_at(&phases, out, dither);
+  }
+
+  /// As render_rgba but writes one palette index per pixel.
+  pub fn render_indexed(&mut self, out : &mut [u8], dither : bool) {
+    let phases = self.current_phases();
+    self.render_frame_indexed_at(&phases, out, dither);
+  }
+
+  /// Length of one loop of the visible layers in microseconds of tick time,
+  /// the cycle length of the slowest animated one.
+  /// Infinite when none of them are animated.
+  pub fn get_cycle_length_us(&self) -> f64 {
+    let longest = self.layers.iter()
+      .filter(|layer| layer.visible && layer.thresh_t_mult != 0.0)
+      .map(|layer| layer.cycle_length_us())
+      .fold(0.0, f64::max);
+
+    if longest > 0.0 {
+      longest
+    }
+    else {
+      f64::INFINITY
     }
   }
 
-  pub fn tick(&mut self, microseconds: u32) {
-    self.t += microseconds;
-    self.data.flush();
+  /// How far through the current cycle of the active layer's wave we are, in [0, 1).
+  pub fn get_phase(&self) -> f64 {
+    self.layers[self.active_layer].phase(self.t)
+  }
+
+  /// Render frame k of an n frame loop, k / n of the way through get_cycle_length_us.
+  /// Independent of the time accumulated by tick, so frame n lines up exactly
+  /// with frame 0.
+  pub fn render_frame_rgba(&mut self, k : u32, n : u32, out : &mut [u8], dither : bool) {
+    let phases = self.loop_phases(k, n);
+    self.render_frame_rgba_at(&phases, out, dither);
   }
 
-  pub fn sample_pixel(&mut self, x : u32, y : u32) -> Color {
-    let t = self.t as f32 * self.thresh_t_mult;
-    let t_y_var = t + TAU * (y as f32) / self.height as f32;
+  /// As render_frame_rgba but writes one palette index per pixel.
+  pub fn render_frame_indexed(&mut self, k : u32, n : u32, out : &mut [u8], dither : bool) {
+    let phases = self.loop_phases(k, n);
+    self.render_frame_indexed_at(&phases, out, dither);
+  }
 
-    let thresh = self.thresh_base + self.thresh_t_var * (t_y_var).sin();
-    let point_data = self.data.get(x, y);
-    point_data.sample(&mut self.rng, thresh, 0.05)
+  /// Render one seamless loop of the threshold waves as a gif in the canvas
+  /// palette, see capture.rs.
+  pub fn encode_gif(&mut self, dither : bool) -> Vec<u8> {
+    capture::encode_gif(self, dither)
   }
 
-  fn fill_draw_buffer_uniform(&mut self, count : usize) {
+  fn fill_draw_buffer_uniform(&mut self, count : usize, phases : &[f64]) {
     for i in 0..count {
-      let x = self.rng.next_u32() % self.width;
-      let y = self.rng.next_u32() % self.height;
-      let col = self.sample_pixel(x, y);
-      self.draw_buffer[i] = DrawPoint {
-        x : x as u8,
-        y : y as u8,
-        col : col
-      }
+      let x = self.position_rng.next_u32() % self.width;
+      let y = self.position_rng.next_u32() % self.height;
+      let col = self.sample_pixel_at(phases, x, y);
+      self.draw_buffer.set(i, x, y, col);
     }
   }
 
   pub fn fill_draw_buffer(&mut self) {
-    // Check to see if pointdata was written to in the last frame
-    let rect = self.data.get_last_dirty().clone();
-    if rect.is_clean() {
+    let phases = self.current_phases();
+
+    // Check to see if any layer was written to in the last frame
+    let rect = self.layers.iter()
+      .fold(DirtyRect::default(), |rect, layer| rect.union(layer.data.get_last_dirty()));
+    if rect.is_empty() {
       // If not sample uniformly over the entire canvas
-      self.fill_draw_buffer_uniform(self.draw_buffer.len());
+      self.fill_draw_buffer_uniform(self.draw_buffer.len(), &phases);
     }
     else {
       // Otherwise reserve some of the buffer to fill with data from the dirty
@@ -144,21 +294,17 @@ impl BlobCanvas {
       // TODO take the ratio using the relative sizes of the dirty rect and
       // the overall canvas.
       let count = ((self.draw_buffer.len() as f32) * 0.95) as usize;
-      self.fill_draw_buffer_uniform(count);
+      self.fill_draw_buffer_uniform(count, &phases);
 
-      // We know this is safe to do because is_clean() check failed.
+      // We know this is safe to do because is_empty() check failed.
       let x_range = rect.max_x - rect.min_x;
       let y_range = rect.max_y - rect.min_y;
 
       for i in count..self.draw_buffer.len() {
-        let x = rect.min_x + (self.rng.next_u32() % x_range);
-        let y = rect.min_y + (self.rng.next_u32() % y_range);
-        let col = self.sample_pixel(x, y);
-        self.draw_buffer[i] = DrawPoint {
-          x : x as u8,
-          y : y as u8,
-          col : col
-        }
+        let x = rect.min_x + (self.position_rng.next_u32() % x_range);
+        let y = rect.min_y + (self.position_rng.next_u32() % y_range);
+        let col = self.sample_pixel_at(&phases, x, y);
+        self.draw_buffer.set(i, x, y, col);
       }
     };
   }
@@ -167,51 +313,552 @@ impl BlobCanvas {
     self.draw_buffer.len()
   }
 
+  pub fn is_draw_buffer_wide(&self) -> bool {
+    self.draw_buffer.is_wide()
+  }
+
+  /// Null if the draw buffer is wide.
   pub fn get_draw_buffer(&self) -> *const DrawPoint {
-      self.draw_buffer.as_ptr()
+    match &self.draw_buffer {
+      DrawBuffer::Narrow(points) => points.as_ptr(),
+      DrawBuffer::Wide(_) => std::ptr::null(),
+    }
   }
 
+  /// Null if the draw buffer is narrow.
+  pub fn get_wide_draw_buffer(&self) -> *const WideDrawPoint {
+    match &self.draw_buffer {
+      DrawBuffer::Narrow(_) => std::ptr::null(),
+      DrawBuffer::Wide(points) => points.as_ptr(),
+    }
+  }
+
+  /// Start a new undo step, everything drawn until the next push_undo
+  /// gets undone together.
   pub fn push_undo(&mut self) {
-    self.undo_stack.push_back(self.data.get_clone());
+    self.commit_paste();
+    self.history.push();
+  }
+
+  /// Undo the last step, it can then be redone with try_pop_redo.
+  pub fn try_pop_undo(&mut self) -> bool {
+    self.commit_paste();
+    let undone = self.history.undo(&mut self.layers, &mut self.selection);
+    self.sync_size();
+    undone
+  }
+
+  /// Redo the last undone step. Redo is lost once anything new is drawn.
+  pub fn try_pop_redo(&mut self) -> bool {
+    self.commit_paste();
+    let redone = self.history.redo(&mut self.layers, &mut self.selection);
+    self.sync_size();
+    redone
+  }
+
+  /// Maximum number of undo steps kept, older ones are dropped.
+  pub fn set_undo_depth(&mut self, depth : usize) {
+    self.history.set_depth(depth);
+  }
+
+  pub fn get_undo_depth(&self) -> usize {
+    self.history.depth()
+  }
+
+  pub fn get_undo_count(&self) -> usize {
+    self.history.len()
+  }
+
+  pub fn get_redo_count(&self) -> usize {
+    self.history.redo_len()
+  }
+
+  /// Mirror every layer left to right.
+  /// With a selection, only the selected points move, see move_selection.
+  pub fn flip_hoz(&mut self) {
+    self.move_layers(PointDataStore::flip_hoz, |u, v| (-u, v), false);
+  }
+
+  /// Mirror every layer top to bottom.
+  pub fn flip_vert(&mut self) {
+    self.move_layers(PointDataStore::flip_vert, |u, v| (u, -v), false);
+  }
+
+  /// Mirror every layer across the diagonal, swapping width and height.
+  pub fn transpose(&mut self) {
+    self.move_layers(PointDataStore::transpose, |u, v| (v, u), false);
+  }
+
+  /// Turn every layer a quarter turn clockwise, swapping width and height.
+  pub fn rotate_90(&mut self) {
+    self.move_layers(PointDataStore::rotate_90, |u, v| (v, -u), false);
+  }
+
+  pub fn rotate_180(&mut self) {
+    self.move_layers(PointDataStore::rotate_180, |u, v| (-u, -v), false);
+  }
+
+  /// Turn every layer a quarter turn anticlockwise, swapping width and height.
+  pub fn rotate_270(&mut self) {
+    self.move_layers(PointDataStore::rotate_270, |u, v| (-v, u), false);
+  }
+
+  /// Turn every layer `radians` clockwise about the centre, keeping the size.
+  /// Corners turned in from outside are empty, unless wrapping.
+  pub fn rotate(&mut self, radians : f32) {
+    let wrap = self.wrap;
+    let (sin, cos) = radians.sin_cos();
+    self.move_layers(
+      |data| data.rotate(radians, wrap, PointData::new(0.0, 0.5)),
+      |u, v| (cos * u + sin * v, cos * v - sin * u),
+      true);
+  }
+
+  /// Restrict brushes, clear and transforms to the points inside the rect
+  /// between two corners, in normalized coordinates.
+  pub fn select_rect(&mut self, x0_norm : f32, y0_norm : f32, x1_norm : f32, y1_norm : f32, op : SelectionOp) {
+    let (w, h) = (self.width as f32, self.height as f32);
+    let selection = Selection::rect(self.width, self.height, x0_norm * w, y0_norm * h, x1_norm * w, y1_norm * h);
+    self.combine_selection(selection, op);
+  }
+
+  /// As select_rect, with the ellipse that fills the rect.
+  pub fn select_ellipse(&mut self, x0_norm : f32, y0_norm : f32, x1_norm : f32, y1_norm : f32, op : SelectionOp) {
+    let (w, h) = (self.width as f32, self.height as f32);
+    let selection = Selection::ellipse(self.width, self.height, x0_norm * w, y0_norm * h, x1_norm * w, y1_norm * h);
+    self.combine_selection(selection, op);
+  }
+
+  /// Select inside a freehand outline, `points` holding normalized x, y pairs.
+  /// The outline is closed from the last point back to the first.
+  pub fn select_lasso(&mut self, points : &[f32], op : SelectionOp) {
+    let (w, h) = (self.width as f32, self.height as f32);
+    let points : Vec<(f32, f32)> = points.chunks_exact(2)
+      .map(|p| (p[0] * w, p[1] * h))
+      .collect();
+    let selection = Selection::lasso(self.width, self.height, &points);
+    self.combine_selection(selection, op);
+  }
+
+  /// Magic wand, select the points connected to this one on the active layer
+  /// with a thresh_band within `tolerance` of it.
+  pub fn select_by_threshold(&mut self, x_norm : f32, y_norm : f32, tolerance : f32, op : SelectionOp) {
+    let (x, y) = self.pixel_at(x_norm, y_norm);
+    let data = &self.layers[self.active_layer].data;
+    let target = data.get(x, y).thresh_band;
+    let selection = Selection::flood(self.width, self.height, x, y, self.wrap, |x, y| {
+      (data.get(x, y).thresh_band - target).abs() <= tolerance
+    });
+    self.combine_selection(selection, op);
+  }
+
+  /// Flood fill on the active layer from a point, through every point connected
+  /// to it on the same side of the threshold as it is drawn right now, so
+  /// clicking inside a blob fills the blob and clicking outside fills the
+  /// space around it. Stops at the selection, wraps with the canvas.
+  pub fn fill(&mut self, x_norm : f32, y_norm : f32, thresh_band : f32, color_band : f32, bands : FillBands) {
+    self.push_undo();
+    let (x, y) = self.pixel_at(x_norm, y_norm);
+    let ctx = RenderContext {
+      width : self.width,
+      height : self.height,
+      wrap : self.wrap,
+      ramp : &self.ramp,
+      outline_width : self.outline_width,
+    };
+    let active = self.active_layer;
+    let layer = &self.layers[active];
+    let phase = layer.phase(self.t);
+    let inside = |x, y| {
+      let p = layer.data.get(x, y);
+      p.thresh_band > layer.thresh_at(phase, x, y, &ctx, &p)
+    };
+
+    let target = inside(x, y);
+    let region = Selection::flood(ctx.width, ctx.height, x, y, ctx.wrap, |x, y| {
+      inside(x, y) == target && self.is_selected(x, y)
+    });
+    let rect = region.bounds();
+    if rect.is_empty() {
+      return;
+    }
+
+    let data = &mut self.layers[active].data;
+    self.history.record(active, data, &rect);
+    data.extend_dirty(rect);
+    for y in rect.min_y..rect.max_y {
+      for x in rect.min_x..rect.max_x {
+        if !region.contains(x, y) {
+          continue;
+        }
 
-    while self.undo_stack.len() > MAX_UNDOS {
-      let _ = self.undo_stack.pop_front();
+        let p = data.get_mut(x, y);
+        match bands {
+          FillBands::Both => {
+            p.thresh_band = clamp_unit(thresh_band);
+            p.color_band = clamp_unit(color_band);
+          },
+          FillBands::Thresh => p.thresh_band = clamp_unit(thresh_band),
+          FillBands::Color => p.color_band = clamp_unit(color_band),
+        }
+      }
     }
   }
 
-  pub fn try_pop_undo(&mut self) -> bool {
-    match self.undo_stack.pop_back() {
-      Some(data) => {
-        self.data = PointDataStore::new(data, self.width, self.height);
+  /// Copy the selected points of the active layer, or all of it without a
+  /// selection. False if nothing is selected.
+  pub fn copy(&mut self) -> bool {
+    let data = &self.layers[self.active_layer].data;
+    match Clip::copy(data, self.selection.as_ref()) {
+      Some(clip) => {
+        self.clipboard = Some(clip);
         true
       },
       _ => false,
     }
   }
 
-  pub fn flip_hoz(&mut self) {
-    self.data.flip_hoz();
+  /// Copy, then clear what was copied.
+  pub fn cut(&mut self) -> bool {
+    self.commit_paste();
+    if !self.copy() {
+      return false;
+    }
+
+    self.clear();
+    true
+  }
+
+  /// Float the clipboard over the active layer where it was copied from,
+  /// to be moved with set_paste_position and flipped before commit_paste.
+  /// False if nothing has been copied.
+  pub fn paste(&mut self) -> bool {
+    let clip = match self.clipboard.as_ref() {
+      Some(clip) => clip.clone(),
+      None => return false,
+    };
+
+    self.push_undo();
+    let mut paste = FloatingPaste::new(clip, self.active_layer);
+    paste.place(&mut self.layers[paste.layer].data, &mut self.history);
+    self.paste = Some(paste);
+    true
+  }
+
+  pub fn is_pasting(&self) -> bool {
+    self.paste.is_some()
+  }
+
+  /// Centre the floating paste on a normalized point.
+  pub fn set_paste_position(&mut self, x_norm : f32, y_norm : f32) {
+    let (px, py) = ((x_norm * self.width as f32).floor() as i32, (y_norm * self.height as f32).floor() as i32);
+    self.update_paste(|paste| {
+      paste.x = px - paste.clip.width() as i32 / 2;
+      paste.y = py - paste.clip.height() as i32 / 2;
+    });
+  }
+
+  pub fn flip_paste_hoz(&mut self) {
+    self.update_paste(|paste| paste.clip.flip_hoz());
+  }
+
+  pub fn flip_paste_vert(&mut self) {
+    self.update_paste(|paste| paste.clip.flip_vert());
+  }
+
+  /// Leave the floating paste where it is. Anything else that edits the canvas
+  /// commits it first.
+  pub fn commit_paste(&mut self) {
+    self.paste = None;
   }
 
+  /// Take the floating paste away again.
+  pub fn cancel_paste(&mut self) {
+    if let Some(mut paste) = self.paste.take() {
+      paste.lift(&mut self.layers[paste.layer].data);
+      // The canvas is back as it was, nothing to undo.
+      self.history.discard();
+    }
+  }
+
+  /// Drop the selection, so everything can be edited again.
+  pub fn select_all(&mut self) {
+    self.selection = None;
+  }
+
+  /// Swap what is selected and what isn't.
+  pub fn invert_selection(&mut self) {
+    let mut selection = self.selection.take()
+      .unwrap_or_else(|| Selection::from_fn(self.width, self.height, |_, _| true));
+    selection.invert();
+    self.selection = Some(selection);
+  }
+
+  pub fn has_selection(&self) -> bool {
+    self.selection.is_some()
+  }
+
+  pub fn is_selected(&self, x : u32, y : u32) -> bool {
+    self.selection.as_ref().is_none_or(|s| s.contains(x, y))
+  }
+
+  /// Change the canvas size without stretching, `anchor_x` and `anchor_y` pick
+  /// which part stays put, 0 for the left or top edge, 0.5 for the middle and
+  /// 1 for the right or bottom. Space added is filled with fresh noise.
+  /// False, with nothing changed, if the size is out of range.
+  pub fn resize_canvas(&mut self, width : u32, height : u32, anchor_x : f32, anchor_y : f32) -> bool {
+    let offset_x = ((width as f32 - self.width as f32) * anchor_x.clamp(0.0, 1.0)).round() as i32;
+    let offset_y = ((height as f32 - self.height as f32) * anchor_y.clamp(0.0, 1.0)).round() as i32;
+    self.resize_layers(width, height, offset_x, offset_y)
+  }
+
+  /// Cut the canvas down to the rect at (x, y), in pixels.
+  /// Parts of the rect off the canvas are filled with fresh noise.
+  pub fn crop_canvas(&mut self, x : i32, y : i32, width : u32, height : u32) -> bool {
+    self.resize_layers(width, height, -x, -y)
+  }
+
+  /// Stretch the canvas to a new size, resampling every layer.
+  pub fn scale_canvas(&mut self, width : u32, height : u32) -> bool {
+    if check_size(width, height).is_err() {
+      return false;
+    }
+
+    self.transform_layers(|data| data.scale(width, height));
+    true
+  }
+
+  /// Width of the canvas, which quarter turns can swap with the height.
+  #[wasm_bindgen(getter)]
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// Clears the active layer, or just the selection on it.
   pub fn clear(&mut self) {
     self.push_undo();
-    self.data = empty_canvas_data(self.width, self.height, &mut self.rng)
+    let active = self.active_layer;
+    let selection = match self.selection.as_ref() {
+      Some(selection) => selection,
+      None => {
+        self.history.record(active, &self.layers[active].data, &DirtyRect::all_dirty(self.width, self.height));
+        self.layers[active].data = empty_canvas_data(self.width, self.height, &mut self.noise_rng);
+        return;
+      },
+    };
+
+    // Just the selected points, with fresh noise as in empty_canvas_data.
+    let rect = selection.bounds();
+    let data = &mut self.layers[active].data;
+    self.history.record(active, data, &rect);
+    data.extend_dirty(rect);
+    for y in rect.min_y..rect.max_y {
+      for x in rect.min_x..rect.max_x {
+        if selection.contains(x, y) {
+          *data.get_mut(x, y) = PointData::new(0.0, rand_unit(&mut self.noise_rng));
+        }
+      }
+    }
+  }
+
+  pub fn get_layer_count(&self) -> usize {
+    self.layers.len()
+  }
+
+  pub fn get_active_layer(&self) -> usize {
+    self.active_layer
+  }
+
+  /// Brushes, clear and the threshold setters all act on the active layer.
+  pub fn set_active_layer(&mut self, index : usize) -> bool {
+    if index < self.layers.len() {
+      self.active_layer = index;
+      true
+    }
+    else {
+      false
+    }
+  }
+
+  /// Add an empty layer on top with the default wave and make it active.
+  /// Returns its index.
+  pub fn add_layer(&mut self) -> usize {
+    let data = empty_canvas_data(self.width, self.height, &mut self.noise_rng);
+    self.layers.push(Layer::new(data));
+    self.active_layer = self.layers.len() - 1;
+    self.active_layer
+  }
+
+  /// Remove a layer, there is always at least one.
+  /// Clears the undo history, as it refers to layers by index.
+  pub fn remove_layer(&mut self, index : usize) -> bool {
+    if index >= self.layers.len() || self.layers.len() == 1 {
+      return false;
+    }
+
+    self.commit_paste();
+    let _ = self.layers.remove(index);
+    if self.active_layer > index || self.active_layer == self.layers.len() {
+      self.active_layer -= 1;
+    }
+    self.end_stroke();
+    self.history.clear();
+    true
+  }
+
+  /// Move a layer to another position in the stack, the active layer moves with it.
+  /// Clears the undo history, as it refers to layers by index.
+  pub fn move_layer(&mut self, from : usize, to : usize) -> bool {
+    if from >= self.layers.len() || to >= self.layers.len() {
+      return false;
+    }
+
+    self.commit_paste();
+    let active_moved = self.active_layer == from;
+    let layer = self.layers.remove(from);
+    self.layers.insert(to, layer);
+    if active_moved {
+      self.active_layer = to;
+    }
+    else if from < self.active_layer && self.active_layer <= to {
+      self.active_layer -= 1;
+    }
+    else if to <= self.active_layer && self.active_layer < from {
+      self.active_layer += 1;
+    }
+    self.end_stroke();
+    self.history.clear();
+    true
+  }
+
+  /// Hidden layers are skipped when compositing.
+  pub fn set_layer_visible(&mut self, index : usize, visible : bool) {
+    if let Some(layer) = self.layers.get_mut(index) {
+      layer.visible = visible;
+    }
+  }
+
+  pub fn is_layer_visible(&self, index : usize) -> bool {
+    self.layers.get(index).is_some_and(|layer| layer.visible)
   }
 
   pub fn set_thresh_base(&mut self, val : f32) {
-    self.thresh_base = val;
+    self.layers[self.active_layer].thresh_base = val;
   }
 
   pub fn set_thresh_t_var(&mut self, val : f32) {
-    self.thresh_t_var = val;
+    self.layers[self.active_layer].thresh_t_var = val;
   }
 
   pub fn set_thresh_t_mult(&mut self, val : f32) {
-    self.thresh_t_mult = val;
+    self.layers[self.active_layer].thresh_t_mult = val;
   }
 
   pub fn get_thresh_t_mult(&self) -> f32 {
-    self.thresh_t_mult
+    self.layers[self.active_layer].thresh_t_mult
+  }
+
+  /// Palette indices color_band runs along, from 0 to 1.
+  /// Ignored unless there are between 1 and 255.
+  pub fn set_inks(&mut self, inks : &[u8]) {
+    if !inks.is_empty() && inks.len() <= u8::MAX as usize {
+      self.ramp.inks = inks.to_vec();
+    }
+  }
+
+  pub fn get_inks(&self) -> Vec<u8> {
+    self.ramp.inks.clone()
+  }
+
+  /// Palette index drawn where a point is near the threshold.
+  pub fn set_outline_color(&mut self, index : u8) {
+    self.ramp.outline = index;
+  }
+
+  pub fn get_outline_color(&self) -> u8 {
+    self.ramp.outline
+  }
+
+  /// Palette index drawn where no layer has ink.
+  pub fn set_background_color(&mut self, index : u8) {
+    self.ramp.background = index;
+  }
+
+  pub fn get_background_color(&self) -> u8 {
+    self.ramp.background
+  }
+
+  /// Number of palette colours the inks, outline and background refer to.
+  /// Any the palette is missing render black.
+  pub fn get_palette_size(&self) -> usize {
+    self.ramp.palette_size()
+  }
+
+  /// How close to the threshold, in thresh_band, a point draws as outline.
+  /// Scaled per point by line_weight, painted with a line weight brush.
+  pub fn set_outline_width(&mut self, width : f32) {
+    self.outline_width = width.max(0.0);
+  }
+
+  pub fn get_outline_width(&self) -> f32 {
+    self.outline_width
+  }
+
+  /// Colours used by rendering and export, and saved with the canvas.
+  pub fn set_palette(&mut self, palette : &Palette) {
+    self.palette = palette.clone();
+  }
+
+  pub fn get_palette(&self) -> Palette {
+    self.palette.clone()
+  }
+
+  /// Direction the active layer's wave travels in, radians from the x axis.
+  /// The default of pi / 2 rolls vertically.
+  pub fn set_wave_direction(&mut self, radians : f32) {
+    self.layers[self.active_layer].wave.direction = radians;
+  }
+
+  pub fn get_wave_direction(&self) -> f32 {
+    self.layers[self.active_layer].wave.direction
+  }
+
+  /// Cycles of the active layer's wave across the canvas.
+  pub fn set_wave_frequency(&mut self, cycles : f32) {
+    self.layers[self.active_layer].wave.frequency = cycles;
+  }
+
+  pub fn get_wave_frequency(&self) -> f32 {
+    self.layers[self.active_layer].wave.frequency
+  }
+
+  /// Ripple out from the wave centre instead of travelling in the wave direction.
+  pub fn set_wave_radial(&mut self, radial : bool) {
+    self.layers[self.active_layer].wave.radial = radial;
+  }
+
+  pub fn is_wave_radial(&self) -> bool {
+    self.layers[self.active_layer].wave.radial
+  }
+
+  /// Centre of a radial wave, in normalized coordinates.
+  pub fn set_wave_centre(&mut self, x_norm : f32, y_norm : f32) {
+    let wave = &mut self.layers[self.active_layer].wave;
+    wave.centre_x = x_norm;
+    wave.centre_y = y_norm;
+  }
+
+  pub fn set_waveform(&mut self, waveform : Waveform) {
+    self.layers[self.active_layer].wave.waveform = waveform;
+  }
+
+  pub fn get_waveform(&self) -> Waveform {
+    self.layers[self.active_layer].wave.waveform
   }
 
   pub fn apply_brush(&mut self, x_norm : f32, y_norm : f32, brush : &Brush) {
@@ -221,30 +868,478 @@ impl BlobCanvas {
   pub fn remove_brush(&mut self, x_norm : f32, y_norm : f32, brush : &Brush) {
     self.apply_brush_internal(x_norm, y_norm, brush, true);
   }
+
+  /// Start a stroke as its own undo step, stamping once at the start point.
+  pub fn begin_stroke(&mut self, x_norm : f32, y_norm : f32, brush : &Brush, remove : bool) {
+    self.begin_stroke_with_pressure(x_norm, y_norm, brush, remove, 1.0, 0.0);
+  }
+
+  /// Continue the stroke, stamping evenly along the path from the last point
+  /// so it stays continuous however far the pointer moved this frame.
+  /// Does nothing outside of begin_stroke / end_stroke.
+  pub fn stroke_to(&mut self, x_norm : f32, y_norm : f32, brush : &Brush) {
+    self.stroke_to_with_pressure(x_norm, y_norm, brush, 1.0, 0.0);
+  }
+
+  /// As begin_stroke with pen pressure and tilt, both 0 to 1, feeding the brush dynamics.
+  pub fn begin_stroke_with_pressure(&mut self, x_norm : f32, y_norm : f32, brush : &Brush, remove : bool, pressure : f32, tilt : f32) {
+    self.push_undo();
+    let (x, y) = (x_norm * self.width as f32, y_norm * self.height as f32);
+    let input = StampInput {
+      pressure : pressure,
+      tilt : tilt,
+      velocity : 0.0,
+    };
+
+    self.stroke = Some(Stroke::new(x, y, input, remove));
+    self.stamp(x.floor() as i32, y.floor() as i32, &brush.modulated(&input), remove);
+  }
+
+  /// As stroke_to with pen pressure and tilt, interpolated from the last sample.
+  pub fn stroke_to_with_pressure(&mut self, x_norm : f32, y_norm : f32, brush : &Brush, pressure : f32, tilt : f32) {
+    let (x, y) = (x_norm * self.width as f32, y_norm * self.height as f32);
+    let input = StampInput {
+      pressure : pressure,
+      tilt : tilt,
+      velocity : 0.0,
+    };
+
+    // Space by the size under pressure, so a thin tapered tip doesn't break into dots.
+    let spacing = (brush.modulated(&input).size.min(brush.size) * STROKE_SPACING).max(1.0);
+
+    let (stamps, remove) = match self.stroke.as_mut() {
+      Some(stroke) => (stroke.advance(x, y, input, spacing, brush.size), stroke.is_remove()),
+      None => return,
+    };
+
+    for (sx, sy, input) in stamps {
+      self.stamp(sx.floor() as i32, sy.floor() as i32, &brush.modulated(&input), remove);
+    }
+  }
+
+  pub fn end_stroke(&mut self) {
+    self.stroke = None;
+  }
+
+  /// Repeat every stamp across mirror axes or around the symmetry centre.
+  pub fn set_symmetry(&mut self, mode : SymmetryMode) {
+    self.symmetry.mode = mode;
+  }
+
+  pub fn get_symmetry(&self) -> SymmetryMode {
+    self.symmetry.mode
+  }
+
+  /// Number of repeats in radial symmetry, including the original.
+  pub fn set_symmetry_folds(&mut self, folds : u32) {
+    self.symmetry.folds = folds.max(1);
+  }
+
+  pub fn get_symmetry_folds(&self) -> u32 {
+    self.symmetry.folds
+  }
+
+  /// Point the mirror axes pass through and radial symmetry turns around,
+  /// in normalized coordinates.
+  pub fn set_symmetry_centre(&mut self, x_norm : f32, y_norm : f32) {
+    self.symmetry.centre_x = x_norm;
+    self.symmetry.centre_y = y_norm;
+  }
+
+  /// Wrap the canvas round so it tiles seamlessly. Stamps and smudges off one
+  /// edge carry on from the opposite edge, and the threshold wave is snapped
+  /// to a whole number of cycles across the canvas.
+  pub fn set_wrap(&mut self, wrap : bool) {
+    self.wrap = wrap;
+  }
+
+  pub fn is_wrap(&self) -> bool {
+    self.wrap
+  }
 }
 
 impl BlobCanvas {
-  pub fn apply_brush_internal(& mut self, x_norm : f32, y_norm : f32, brush : &Brush, remove : bool) {
+  /// Canvases larger than 256 in either dimension get a wide draw buffer.
+  pub fn new(width : u32, height: u32) -> Result<Self, SizeError> {
+    Self::new_with_seed(width, height, RAND_SEED)
+  }
 
-    let rad = (brush.size / 2.0) as i32;
+  /// A canvas whose noise, dithering and draw buffer positions all derive from
+  /// `seed`, so the same seed always produces the same frames.
+  pub fn new_with_seed(width : u32, height: u32, seed : u64) -> Result<Self, SizeError> {
+    let wide = width > MAX_NARROW_DIMENSION || height > MAX_NARROW_DIMENSION;
+    Self::new_with_buffer(width, height, seed, wide)
+  }
+
+  /// Always use a wide draw buffer, regardless of size.
+  pub fn new_wide(width : u32, height: u32) -> Result<Self, SizeError> {
+    Self::new_with_buffer(width, height, RAND_SEED, true)
+  }
+
+  /// Phase of each layer at the current time.
+  fn current_phases(&self) -> Vec<f64> {
+    self.layers.iter().map(|layer| layer.phase(self.t)).collect()
+  }
+
+  /// Phase of each layer for frame k in an n frame loop.
+  /// Faster layers are rounded to a whole number of cycles per loop so every
+  /// layer lines up again at frame n, run backwards for a negative
+  /// thresh_t_mult to match tick, and stay put when they aren't animated.
+  fn loop_phases(&self, k : u32, n : u32) -> Vec<f64> {
+    assert!(n > 0, "a loop needs at least one frame");
+    let f = (k % n) as f64 / n as f64;
+    let loop_us = self.get_cycle_length_us();
+
+    self.layers.iter()
+      .map(|layer| {
+        if layer.thresh_t_mult == 0.0 || !loop_us.is_finite() {
+          return 0.0;
+        }
+
+        let cycles = (loop_us / layer.cycle_length_us()).round().max(1.0);
+        let phase = (f * cycles).fract();
+        if layer.thresh_t_mult > 0.0 {
+          phase
+        }
+        else {
+          1.0 - phase
+        }
+      })
+      .collect()
+  }
+
+  fn render_frame_rgba_at(&mut self, phases : &[f64], out : &mut [u8], dither : bool) {
+    let palette = self.palette.to_rgb_padded(self.ramp.palette_size());
+    assert_eq!(out.len(), self.width as usize * self.height as usize * 4, "output must be width * height rgba pixels");
 
+    let mut i = 0;
+    self.render_with(phases, dither, |col| {
+      let c = col as usize * 3;
+      out[i..i+3].copy_from_slice(&palette[c..c+3]);
+      out[i+3] = 0xFF;
+      i += 4;
+    });
+  }
+
+  fn render_frame_indexed_at(&mut self, phases : &[f64], out : &mut [u8], dither : bool) {
+    assert_eq!(out.len(), self.width as usize * self.height as usize, "output must be width * height pixels");
+
+    let mut i = 0;
+    self.render_with(phases, dither, |col| {
+      out[i] = col;
+      i += 1;
+    });
+  }
+
+  /// As sample_pixel, with each layer's wave at the given phase.
+  fn sample_pixel_at(&mut self, phases : &[f64], x : u32, y : u32) -> u8 {
+    let ctx = RenderContext {
+      width : self.width,
+      height : self.height,
+      wrap : self.wrap,
+      ramp : &self.ramp,
+      outline_width : self.outline_width,
+    };
+    let rng = &mut self.dither_rng;
+    composite(&self.layers, &ctx, x, y, phases, || rand_unit(rng))
+  }
+
+  /// Sample every pixel in row order, with each layer's wave at the given phase.
+  fn render_with<F : FnMut(u8)>(&mut self, phases : &[f64], dither : bool, mut f : F) {
+    let ctx = RenderContext {
+      width : self.width,
+      height : self.height,
+      wrap : self.wrap,
+      ramp : &self.ramp,
+      outline_width : self.outline_width,
+    };
+    for y in 0..ctx.height {
+      for x in 0..ctx.width {
+        let col = if dither {
+          let rng = &mut self.dither_rng;
+          composite(&self.layers, &ctx, x, y, phases, || rand_unit(rng))
+        }
+        else {
+          composite(&self.layers, &ctx, x, y, phases, || 0.5)
+        };
+
+        f(col);
+      }
+    }
+  }
+
+  fn new_with_buffer(width : u32, height : u32, seed : u64, wide : bool) -> Result<Self, SizeError> {
+    check_size(width, height)?;
+
+    let mut noise_rng = stream_rng(seed, RngStream::Noise);
+    let canvas_data = empty_canvas_data(width, height, &mut noise_rng);
+    Ok(Self::from_parts(width, height, vec![Layer::new(canvas_data)], seed, noise_rng, wide))
+  }
+
+  fn from_parts(width : u32, height : u32, layers : Vec<Layer>, seed : u64, noise_rng : XorShiftRng, wide : bool) -> Self {
+    let draw_buffer = DrawBuffer::new(draw_buffer_len(width, height), wide);
+
+    BlobCanvas {
+      width : width,
+      height : height,
+      layers : layers,
+      active_layer : 0,
+      ramp : ColorRamp::default(),
+      palette : Palette::default(),
+      outline_width : DEFAULT_OUTLINE_WIDTH,
+      history : UndoHistory::new(DEFAULT_UNDO_DEPTH),
+      stroke : None,
+      symmetry : Symmetry::default(),
+      selection : None,
+      clipboard : None,
+      paste : None,
+      wrap : false,
+      draw_buffer: draw_buffer,
+      t : 0,
+      seed : seed,
+      noise_rng : noise_rng,
+      dither_rng : stream_rng(seed, RngStream::Dither),
+      position_rng : stream_rng(seed, RngStream::Position),
+    }
+  }
+
+  pub fn from_rgba(data : &[u8], width : u32, height : u32, settings : &ImportSettings) -> Result<Self, ImportError> {
+    import::import_rgba(data, width, height, settings).map(Self::from_store)
+  }
+
+  pub fn from_grey(data : &[u8], width : u32, height : u32, settings : &ImportSettings) -> Result<Self, ImportError> {
+    import::import_grey(data, width, height, settings).map(Self::from_store)
+  }
+
+  /// A canvas traced from a png file, the size of the image.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn from_png(bytes : &[u8], settings : &ImportSettings) -> Result<Self, ImportError> {
+    import::import_png(bytes, settings).map(Self::from_store)
+  }
+
+  /// A single layer canvas around imported points.
+  fn from_store(store : PointDataStore) -> Self {
+    let (width, height) = (store.get_width(), store.get_height());
+    let wide = width > MAX_NARROW_DIMENSION || height > MAX_NARROW_DIMENSION;
+    let noise_rng = stream_rng(RAND_SEED, RngStream::Noise);
+    Self::from_parts(width, height, vec![Layer::new(store)], RAND_SEED, noise_rng, wide)
+  }
+
+  pub fn deserialize(data : &[u8]) -> Result<Self, DocumentError> {
+    let doc = Document::read(data)?;
+    let noise_rng = stream_rng(doc.seed, RngStream::Noise);
+    let (width, height) = (doc.width, doc.height);
+    let layers = doc.layers.into_iter()
+      .map(|layer_doc| {
+        let mut layer = Layer::new(PointDataStore::new(layer_doc.points, width, height));
+        layer.thresh_base = layer_doc.thresh_base;
+        layer.thresh_t_var = layer_doc.thresh_t_var;
+        layer.thresh_t_mult = layer_doc.thresh_t_mult;
+        layer.wave = layer_doc.wave;
+        layer.visible = layer_doc.visible;
+        layer
+      })
+      .collect();
+
+    let wide = width > MAX_NARROW_DIMENSION || height > MAX_NARROW_DIMENSION;
+    let mut canvas = Self::from_parts(width, height, layers, doc.seed, noise_rng, wide);
+    canvas.active_layer = doc.active_layer as usize;
+    canvas.ramp = doc.ramp;
+    canvas.palette = doc.palette;
+    canvas.outline_width = doc.outline_width;
+    canvas.wrap = doc.wrap;
+    canvas.t = doc.t;
+    Ok(canvas)
+  }
+
+  pub fn palette(&self) -> &Palette {
+    &self.palette
+  }
+
+  /// Native counterpart to get_draw_buffer, valid after fill_draw_buffer.
+  pub fn draw_buffer(&self) -> &DrawBuffer {
+    &self.draw_buffer
+  }
+
+  /// Apply a flip or rotation to every layer as one undo step. With a selection
+  /// only the selected points move, about the middle of the selection, with
+  /// point (u, v) from the middle coming from `source(u, v)`. The selection
+  /// moves with them.
+  fn move_layers<F, S>(&mut self, f : F, source : S, smooth : bool)
+    where F : FnMut(&mut PointDataStore), S : Fn(f32, f32) -> (f32, f32)
+  {
+    self.commit_paste();
+    let selection = match self.selection.take() {
+      Some(selection) => selection,
+      None => return self.transform_layers(f),
+    };
+
+    let bounds = selection.bounds();
+    let cx = (bounds.min_x + bounds.max_x) as f32 / 2.0 - 0.5;
+    let cy = (bounds.min_y + bounds.max_y) as f32 / 2.0 - 0.5;
+    let source = |x : f32, y : f32| {
+      let (u, v) = source(x - cx, y - cy);
+      (u + cx, v + cy)
+    };
+    let moved = Selection::from_fn(self.width, self.height, |x, y| {
+      let (sx, sy) = source(x as f32, y as f32);
+      let (sx, sy) = (sx.round(), sy.round());
+      sx >= 0.0 && sy >= 0.0 && selection.contains(sx as u32, sy as u32)
+    });
+
+    self.history.record_canvas(&self.layers, Some(&selection));
+    for layer in self.layers.iter_mut() {
+      layer.data.move_selected(&selection, &moved, source, smooth);
+    }
+    self.selection = Some(moved);
+  }
+
+  /// Lift the floating paste, change it, and put it down again.
+  fn update_paste<F : FnOnce(&mut FloatingPaste)>(&mut self, f : F) {
+    if let Some(paste) = self.paste.as_mut() {
+      let data = &mut self.layers[paste.layer].data;
+      paste.lift(data);
+      f(paste);
+      paste.place(data, &mut self.history);
+    }
+  }
+
+  fn combine_selection(&mut self, selection : Selection, op : SelectionOp) {
+    match self.selection.as_mut() {
+      Some(current) => current.combine(&selection, op),
+      None => {
+        // No selection lets everything be edited, so cutting away from it starts
+        // with everything selected, but adding to it starts from nothing.
+        let all = matches!(op, SelectionOp::Subtract | SelectionOp::Intersect);
+        let mut current = Selection::from_fn(self.width, self.height, |_, _| all);
+        current.combine(&selection, op);
+        self.selection = Some(current);
+      },
+    }
+  }
+
+  /// Pixel under a normalized point, clamped to the canvas.
+  fn pixel_at(&self, x_norm : f32, y_norm : f32) -> (u32, u32) {
+    let x = (x_norm * self.width as f32).floor().clamp(0.0, (self.width - 1) as f32);
+    let y = (y_norm * self.height as f32).floor().clamp(0.0, (self.height - 1) as f32);
+    (x as u32, y as u32)
+  }
+
+  /// Apply an edit to every layer as one undo step.
+  fn transform_layers<F : FnMut(&mut PointDataStore)>(&mut self, mut f : F) {
+    self.commit_paste();
+    self.history.record_canvas(&self.layers, self.selection.as_ref());
+    for layer in self.layers.iter_mut() {
+      f(&mut layer.data);
+    }
+    self.sync_size();
+  }
+
+  fn resize_layers(&mut self, width : u32, height : u32, offset_x : i32, offset_y : i32) -> bool {
+    if check_size(width, height).is_err() {
+      return false;
+    }
+
+    self.commit_paste();
+    self.history.record_canvas(&self.layers, self.selection.as_ref());
+    let noise_rng = &mut self.noise_rng;
+    for layer in self.layers.iter_mut() {
+      layer.data.resize(width, height, offset_x, offset_y, || PointData::new(0.0, rand_unit(noise_rng)));
+    }
+    self.sync_size();
+    true
+  }
+
+  /// Pick up a new canvas size from the layers after a transform or undo,
+  /// resizing the draw buffer to match.
+  fn sync_size(&mut self) {
+    let (width, height) = (self.layers[0].data.get_width(), self.layers[0].data.get_height());
+    // Points no longer line up with a mask of another size, though undo may
+    // have already put back one that fits.
+    match self.selection.as_ref() {
+      Some(selection) if (selection.width(), selection.height()) != (width, height) => self.selection = None,
+      _ => {}
+    }
+    if (width, height) == (self.width, self.height) {
+      return;
+    }
+
+    self.width = width;
+    self.height = height;
+    // Stays wide once wide, JS may have already picked the wide layout.
+    let wide = self.draw_buffer.is_wide() || width > MAX_NARROW_DIMENSION || height > MAX_NARROW_DIMENSION;
+    self.draw_buffer = DrawBuffer::new(draw_buffer_len(width, height), wide);
+  }
+
+  pub fn apply_brush_internal(& mut self, x_norm : f32, y_norm : f32, brush : &Brush, remove : bool) {
+    self.commit_paste();
     let px = (x_norm * (self.width as f32)).floor() as i32;
     let py = (y_norm * (self.height as f32)).floor() as i32;
+    self.stamp(px, py, brush, remove);
+  }
+
+  /// Apply the brush centred on pixel (px, py), and on each of its images
+  /// under the symmetry mode.
+  fn stamp(&mut self, px : i32, py : i32, brush : &Brush, remove : bool) {
+    for (x, y, transform) in self.symmetry.images(px, py, self.width, self.height) {
+      if transform == IDENTITY {
+        self.stamp_once(x, y, brush, remove);
+      }
+      else {
+        self.stamp_once(x, y, &brush.transformed(&transform), remove);
+      }
+    }
+  }
+
+  /// Apply the brush once centred on pixel (px, py). When wrapping, the parts
+  /// hanging off the canvas are applied again from the opposite edges.
+  fn stamp_once(&mut self, px : i32, py : i32, brush : &Brush, remove : bool) {
+    if !self.wrap {
+      self.stamp_clipped(px, py, brush, remove);
+      return;
+    }
+
+    let (w, h) = (self.width as i32, self.height as i32);
+    let (px, py) = (px.rem_euclid(w), py.rem_euclid(h));
+    let rad = (brush.size / 2.0) as i32;
+    for oy in [-h, 0, h] {
+      for ox in [-w, 0, w] {
+        let (x, y) = (px + ox, py + oy);
+        if x + rad >= 0 && x - rad < w && y + rad >= 0 && y - rad < h {
+          self.stamp_clipped(x, y, brush, remove);
+        }
+      }
+    }
+  }
+
+  /// Apply the brush centred on pixel (px, py), cut off at the canvas edges.
+  fn stamp_clipped(&mut self, px : i32, py : i32, brush : &Brush, remove : bool) {
+    let rad = (brush.size / 2.0) as i32;
 
-    let x_min = (px - rad).max(0);
-    let x_max = (px + rad).min(self.width as i32);
-    let y_min = (py - rad).max(0);
-    let y_max = (py + rad).min(self.height as i32);
+    // Inclusive of both edges so the stamp is symmetric about (px, py).
+    // Stamps hanging off the canvas get an empty rect rather than a negative one.
+    let x_min = (px - rad).clamp(0, self.width as i32);
+    let x_max = (px + rad + 1).clamp(x_min, self.width as i32);
+    let y_min = (py - rad).clamp(0, self.height as i32);
+    let y_max = (py + rad + 1).clamp(y_min, self.height as i32);
 
-    self.data.set_dirty(DirtyRect {
+    let rect = DirtyRect {
       min_x: x_min as u32,
       min_y: y_min as u32,
       max_x: x_max as u32,
       max_y: y_max as u32,
-    });
+    };
+    let active = self.active_layer;
+    self.history.record(active, &self.layers[active].data, &rect);
+    // A stroke can stamp several times a frame, keep all of them dirty.
+    self.layers[active].data.extend_dirty(rect);
 
     for y in y_min..y_max {
       for x in x_min..x_max {
+        if !self.is_selected(x as u32, y as u32) {
+          continue;
+        }
+
         let api = CanvasApi {
           x : x as u32,
           y : y as u32,
@@ -258,6 +1353,30 @@ impl BlobCanvas {
   }
 }
 
+/// Walk down from the top layer until one isn't background there.
+/// `phases` has the phase of each layer's wave.
+fn composite<F : FnMut() -> f32>(layers : &[Layer], ctx : &RenderContext, x : u32, y : u32, phases : &[f64], mut seed : F) -> u8 {
+  for (layer, &phase) in layers.iter().zip(phases).rev() {
+    if !layer.visible {
+      continue;
+    }
+
+    let point_data = layer.data.get(x, y);
+    let thresh = layer.thresh_at(phase, x, y, ctx, &point_data);
+    match point_data.sample_with(thresh, ctx.outline_width, ctx.ramp, &mut seed) {
+      Some(col) => return col,
+      _ => {},
+    }
+  }
+
+  ctx.ramp.background
+}
+
+/// Points redrawn each frame, a fraction of the canvas.
+fn draw_buffer_len(width : u32, height : u32) -> usize {
+  ((width as f32) * (height as f32) * 0.05) as usize
+}
+
 fn empty_canvas_data(width: u32, height: u32, rng: &mut dyn RngCore) -> PointDataStore {
     let size = width * height;
     let mut data = Vec::with_capacity(size as usize);
@@ -276,12 +1395,78 @@ pub struct CanvasApi<'t> {
 
 impl<'t> CanvasApi<'t>{
   pub fn get_mut(&mut self) -> &mut PointData {
-    self.canvas.data.get_mut(self.x, self.y)
+    let active = self.canvas.active_layer;
+    self.canvas.layers[active].data.get_mut(self.x, self.y)
   }
 
   pub fn try_get_point(&self, xoff : i32, yoff: i32) -> Option<PointData> {
     let x = self.x as i32 + xoff;
     let y = self.y as i32 + yoff;
-    self.canvas.data.try_get(x, y)
+    let data = &self.canvas.layers[self.canvas.active_layer].data;
+    if self.canvas.wrap {
+      Some(data.get_wrapped(x, y))
+    }
+    else {
+      data.try_get(x, y)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn undo_mid_stroke_keeps_recording() {
+    let mut canvas = BlobCanvas::new(64, 64).unwrap();
+    let brush = Brush::new_inv(4, 0.5, 1.0);
+    let blank = canvas.serialize();
+
+    canvas.begin_stroke(0.1, 0.1, &brush, false);
+    canvas.stroke_to(0.3, 0.3, &brush);
+    assert!(canvas.try_pop_undo());
+    assert_eq!(canvas.serialize(), blank);
+
+    // The rest of the stroke is its own step and can be undone in turn.
+    canvas.stroke_to(0.6, 0.6, &brush);
+    canvas.end_stroke();
+    assert_eq!(canvas.get_undo_count(), 1);
+    assert_ne!(canvas.serialize(), blank);
+    assert!(canvas.try_pop_undo());
+    assert_eq!(canvas.serialize(), blank);
+  }
+
+  #[test]
+  fn undo_moves_selection_back() {
+    let mut canvas = BlobCanvas::new(8, 8).unwrap();
+    // A row of three points, turned upright about the middle one.
+    canvas.select_rect(0.0, 0.125, 0.375, 0.25, SelectionOp::Replace);
+    canvas.rotate_90();
+    assert!(canvas.is_selected(1, 0));
+    assert!(!canvas.is_selected(0, 1));
+
+    assert!(canvas.try_pop_undo());
+    assert!(canvas.is_selected(0, 1));
+    assert!(!canvas.is_selected(1, 0));
+    assert!(canvas.try_pop_redo());
+    assert!(canvas.is_selected(1, 0));
+  }
+
+  #[test]
+  fn cancelled_paste_leaves_no_undo() {
+    let mut canvas = BlobCanvas::new(64, 64).unwrap();
+    let brush = Brush::new_inv(4, 0.5, 1.0);
+    canvas.begin_stroke(0.2, 0.2, &brush, false);
+    canvas.end_stroke();
+    canvas.select_rect(0.0, 0.0, 0.5, 0.5, SelectionOp::Replace);
+    assert!(canvas.copy());
+    let before = canvas.serialize();
+
+    canvas.push_undo();
+    assert!(canvas.paste());
+    canvas.set_paste_position(0.75, 0.75);
+    canvas.cancel_paste();
+    assert_eq!(canvas.serialize(), before);
+    assert_eq!(canvas.get_undo_count(), 1);
   }
-}
\ No newline at end of file
+}
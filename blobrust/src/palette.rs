@@ -0,0 +1,247 @@
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+/// Most colours a palette can hold, palette indices are a u8.
+pub const MAX_PALETTE_SIZE : usize = 256;
+
+/// Longest name kept, in bytes, documents store its length as a u16.
+pub const MAX_NAME_LEN : usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+  Empty,
+  TooManyColors(usize),
+  BadColor(String),
+}
+
+impl fmt::Display for PaletteError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PaletteError::Empty => write!(f, "palette has no colours"),
+      PaletteError::TooManyColors(n) => write!(f, "palette has {} colours, at most {} are supported", n, MAX_PALETTE_SIZE),
+      PaletteError::BadColor(s) => write!(f, "can't read colour \"{}\"", s),
+    }
+  }
+}
+
+impl std::error::Error for PaletteError {}
+
+/// Rgb colours indexed by palette index, see ColorRamp.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+  name : String,
+  colors : Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+  /// "Pico 8", the default palette on the site.
+  fn default() -> Self {
+    Palette {
+      name : "Pico 8".to_owned(),
+      colors : vec![
+        [0xff, 0x77, 0xa8],
+        [0x83, 0x76, 0x9c],
+        [0xff, 0xcc, 0xaa],
+        [0x00, 0x00, 0x00],
+      ],
+    }
+  }
+}
+
+#[wasm_bindgen]
+impl Palette {
+  /// Read a GIMP .gpl file, or anything else as a list of hex colours.
+  #[wasm_bindgen(js_name = parse)]
+  pub fn parse_js(text : &str) -> Result<Palette, JsValue> {
+    Self::parse(text).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+
+  /// Hex colours such as "#ff77a8" or "f7a", separated by whitespace or commas.
+  /// Also reads Lospec .hex files, which are one colour per line without the #.
+  #[wasm_bindgen(js_name = from_hex)]
+  pub fn from_hex_js(text : &str) -> Result<Palette, JsValue> {
+    Self::from_hex(text).map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+
+  pub fn get_name(&self) -> String {
+    self.name.clone()
+  }
+
+  /// Names longer than MAX_NAME_LEN are cut short.
+  pub fn set_name(&mut self, name : &str) {
+    self.name = truncate_name(name);
+  }
+
+  pub fn len(&self) -> usize {
+    self.colors.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.colors.is_empty()
+  }
+
+  /// Colour as "#rrggbb", for css.
+  pub fn get_hex(&self, index : usize) -> String {
+    let [r, g, b] = self.rgb(index);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+  }
+
+  /// Flat rgb triples, the layout the gif encoder wants.
+  pub fn to_rgb(&self) -> Vec<u8> {
+    self.colors.iter().flatten().copied().collect()
+  }
+}
+
+impl Palette {
+  pub fn new(name : &str, colors : Vec<[u8; 3]>) -> Result<Self, PaletteError> {
+    if colors.is_empty() {
+      return Err(PaletteError::Empty);
+    }
+    if colors.len() > MAX_PALETTE_SIZE {
+      return Err(PaletteError::TooManyColors(colors.len()));
+    }
+
+    Ok(Palette {
+      name : truncate_name(name),
+      colors : colors,
+    })
+  }
+
+  pub fn parse(text : &str) -> Result<Self, PaletteError> {
+    if text.trim_start().starts_with("GIMP Palette") {
+      Self::from_gpl(text)
+    }
+    else {
+      Self::from_hex(text)
+    }
+  }
+
+  pub fn from_hex(text : &str) -> Result<Self, PaletteError> {
+    let colors = text
+      .split(|c : char| c.is_whitespace() || c == ',')
+      .filter(|s| !s.is_empty())
+      .map(parse_hex)
+      .collect::<Result<Vec<_>, _>>()?;
+
+    Self::new("", colors)
+  }
+
+  /// GIMP palette, a "GIMP Palette" header, optional Name and Columns lines,
+  /// # comments, then "r g b name" per colour.
+  pub fn from_gpl(text : &str) -> Result<Self, PaletteError> {
+    let mut name = "";
+    let mut colors = Vec::new();
+    for line in text.lines().skip(1) {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+        continue;
+      }
+      if let Some(rest) = line.strip_prefix("Name:") {
+        name = rest.trim();
+        continue;
+      }
+
+      let channels = line.split_whitespace()
+        .take(3)
+        .map(|s| s.parse::<u8>().map_err(|_| PaletteError::BadColor(line.to_owned())))
+        .collect::<Result<Vec<_>, _>>()?;
+      match channels.as_slice() {
+        [r, g, b] => colors.push([*r, *g, *b]),
+        _ => return Err(PaletteError::BadColor(line.to_owned())),
+      }
+    }
+
+    Self::new(name, colors)
+  }
+
+  /// Colours past the end of the palette are black.
+  pub fn rgb(&self, index : usize) -> [u8; 3] {
+    self.colors.get(index).copied().unwrap_or([0, 0, 0])
+  }
+
+  /// As to_rgb, padded with black to `count` colours.
+  pub fn to_rgb_padded(&self, count : usize) -> Vec<u8> {
+    (0..count.max(self.colors.len())).flat_map(|i| self.rgb(i)).collect()
+  }
+
+  pub fn colors(&self) -> &[[u8; 3]] {
+    &self.colors
+  }
+}
+
+fn parse_hex(s : &str) -> Result<[u8; 3], PaletteError> {
+  let err = || PaletteError::BadColor(s.to_owned());
+  let hex = s.strip_prefix('#').unwrap_or(s);
+  let digits = hex.chars()
+    .map(|c| c.to_digit(16).map(|d| d as u8))
+    .collect::<Option<Vec<u8>>>()
+    .ok_or_else(err)?;
+
+  match digits.as_slice() {
+    [r, g, b] => Ok([r * 17, g * 17, b * 17]),
+    [r1, r0, g1, g0, b1, b0] => Ok([r1 * 16 + r0, g1 * 16 + g0, b1 * 16 + b0]),
+    _ => Err(err()),
+  }
+}
+
+/// At most MAX_NAME_LEN bytes of `name`, not splitting a character.
+fn truncate_name(name : &str) -> String {
+  let mut len = name.len().min(MAX_NAME_LEN);
+  while !name.is_char_boundary(len) {
+    len -= 1;
+  }
+  name[..len].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reads_gpl() {
+    let text = "GIMP Palette\nName: Sunset\nColumns: 4\n# a comment\n\n255 119 168\tpink\n  0 0 0 black\n";
+    let palette = Palette::parse(text).unwrap();
+    assert_eq!(palette.get_name(), "Sunset");
+    assert_eq!(palette.colors(), &[[255, 119, 168], [0, 0, 0]]);
+  }
+
+  #[test]
+  fn rejects_bad_gpl_lines() {
+    let bad = |line : &str| Palette::parse(&format!("GIMP Palette\n{}\n", line)).unwrap_err();
+    assert_eq!(bad("255 0"), PaletteError::BadColor("255 0".to_owned()));
+    assert_eq!(bad("256 0 0"), PaletteError::BadColor("256 0 0".to_owned()));
+    assert_eq!(Palette::parse("GIMP Palette\n# nothing here\n").unwrap_err(), PaletteError::Empty);
+  }
+
+  #[test]
+  fn reads_hex() {
+    // Lospec .hex files are one colour per line without the #.
+    let palette = Palette::parse("ff77a8\n83769c\r\nFFCCAA\n").unwrap();
+    assert_eq!(palette.colors(), &[[0xff, 0x77, 0xa8], [0x83, 0x76, 0x9c], [0xff, 0xcc, 0xaa]]);
+
+    let palette = Palette::from_hex("#f7a, #000000 fff").unwrap();
+    assert_eq!(palette.colors(), &[[0xff, 0x77, 0xaa], [0, 0, 0], [0xff, 0xff, 0xff]]);
+    assert_eq!(palette.get_hex(0), "#ff77aa");
+  }
+
+  #[test]
+  fn rejects_bad_hex() {
+    assert_eq!(Palette::from_hex("ff77a").unwrap_err(), PaletteError::BadColor("ff77a".to_owned()));
+    assert_eq!(Palette::from_hex("#gg0000").unwrap_err(), PaletteError::BadColor("#gg0000".to_owned()));
+    assert_eq!(Palette::from_hex(" , ").unwrap_err(), PaletteError::Empty);
+    let many = "000 ".repeat(MAX_PALETTE_SIZE + 1);
+    assert_eq!(Palette::from_hex(&many).unwrap_err(), PaletteError::TooManyColors(MAX_PALETTE_SIZE + 1));
+  }
+
+  #[test]
+  fn caps_name_length() {
+    // Two byte characters, the cap falls in the middle of the last one.
+    let name = "é".repeat(MAX_NAME_LEN / 2 + 1);
+    let mut palette = Palette::new(&name, vec![[0, 0, 0]]).unwrap();
+    assert_eq!(palette.get_name().len(), MAX_NAME_LEN - 1);
+    palette.set_name("short");
+    assert_eq!(palette.get_name(), "short");
+  }
+}
@@ -1,13 +1,19 @@
 use std::ptr;
-use rand_core::{RngCore};
 
-use super::Color;
-use super::utils::rand_unit;
+use super::ramp::ColorRamp;
+use super::utils::{wrap_unit, wrap_half};
+use super::selection::Selection;
 
 #[derive(Clone, Copy, Debug)]
 pub struct PointData {
   pub thresh_band : f32,
   pub color_band : f32,
+  /// Scales the layer's wave here, 0 holds still and 1 follows the layer.
+  pub wobble_amp : f32,
+  /// Offsets the layer's wave here, as a fraction of a cycle.
+  pub wobble_phase : f32,
+  /// Scales the canvas outline width here.
+  pub line_weight : f32,
 }
 
 impl PointData {
@@ -15,25 +21,38 @@ impl PointData {
     PointData {
       thresh_band: thresh_band,
       color_band: color_band,
+      wobble_amp: 1.0,
+      wobble_phase: 0.0,
+      line_weight: 1.0,
     }
   }
 
-  pub fn sample(&self, rng: &mut dyn RngCore, thresh : f32, thresh_band : f32) -> Color {
-    if (self.thresh_band - thresh).abs() < thresh_band {
-      return Color::THRESH;
+  /// `k` of the way from self to `other`, wobble_phase going the short way round.
+  pub fn lerp(&self, other : &PointData, k : f32) -> PointData {
+    let mix = |a : f32, b : f32| a + (b - a) * k;
+    PointData {
+      thresh_band : mix(self.thresh_band, other.thresh_band),
+      color_band : mix(self.color_band, other.color_band),
+      wobble_amp : mix(self.wobble_amp, other.wobble_amp),
+      wobble_phase : wrap_unit(self.wobble_phase + wrap_half(other.wobble_phase - self.wobble_phase) * k),
+      line_weight : mix(self.line_weight, other.line_weight),
+    }
+  }
+
+  /// Palette index at this point, None where it is background.
+  /// Points within `outline_width` of the threshold, scaled by line_weight, are outline.
+  /// The dither value comes from `seed` which is only called when choosing
+  /// between inks, pass `|| 0.5` for a flat undithered result.
+  pub fn sample_with<F : FnOnce() -> f32>(&self, thresh : f32, outline_width : f32, ramp : &ColorRamp, seed : F) -> Option<u8> {
+    if (self.thresh_band - thresh).abs() < outline_width * self.line_weight {
+      return Some(ramp.outline);
     }
 
     if self.thresh_band > thresh {
-      let seed = rand_unit(rng);
-      if self.color_band < seed {
-        Color::X
-      }
-      else {
-        Color::Y
-      }
+      Some(ramp.ink(self.color_band, seed))
     }
     else {
-      Color::Z
+      None
     }
   }
 }
@@ -68,6 +87,36 @@ impl DirtyRect {
             max_y : height,
         }
     }
+
+    /// True if the rect covers no points.
+    pub fn is_empty(&self) -> bool {
+        self.max_x <= self.min_x || self.max_y <= self.min_y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.max_x.saturating_sub(self.min_x)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.max_y.saturating_sub(self.min_y)
+    }
+
+    /// Smallest rect covering both, an empty rect adds nothing.
+    pub fn union(&self, other : &DirtyRect) -> DirtyRect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+
+        DirtyRect {
+            min_x : self.min_x.min(other.min_x),
+            min_y : self.min_y.min(other.min_y),
+            max_x : self.max_x.max(other.max_x),
+            max_y : self.max_y.max(other.max_y),
+        }
+    }
 }
 
 /// Contains a mutable and immutable copy of the same data.
@@ -87,14 +136,14 @@ pub struct PointDataStore {
 impl PointDataStore {
     pub fn new(data : Vec<PointData>, width : u32, height : u32) -> Self {
 
-        let dirty = DirtyRect::clean_rect(width as u32, height as u32);
-        let dirty_last = dirty.clone();
+        let dirty = DirtyRect::clean_rect(width, height);
+        let dirty_last = dirty;
 
         PointDataStore {
             mut_data: data.clone(),
             imm_data: data,
-            width : width as u32,
-            height : height as u32,
+            width : width,
+            height : height,
             dirty : dirty,
             dirty_last : dirty_last,
         }
@@ -114,8 +163,6 @@ impl PointDataStore {
     }
 
     pub fn get_mut(&mut self, x : u32, y : u32) -> &mut PointData {
-        debug_assert!(true);
-        debug_assert!(false);
         let i = self.get_index(x, y);
         &mut self.mut_data[i]
     }
@@ -130,10 +177,57 @@ impl PointDataStore {
             .map(|i| self.imm_data[i])
     }
 
+    /// As try_get, but off one edge continues from the opposite edge.
+    pub fn get_wrapped(&self, x : i32, y : i32) -> PointData {
+        let x = x.rem_euclid(self.width as i32) as u32;
+        let y = y.rem_euclid(self.height as i32) as u32;
+        self.get(x, y)
+    }
+
+    /// Swap in new data, possibly of a different size, all of it dirty.
+    pub fn replace(&mut self, data : Vec<PointData>, width : u32, height : u32) {
+        *self = PointDataStore::new(data, width, height);
+        self.dirty = DirtyRect::all_dirty(width, height);
+    }
+
+    #[allow(dead_code)]
     pub fn set_dirty(&mut self, rect : DirtyRect) {
         self.dirty = rect
     }
 
+    /// Like set_dirty but keeps anything already marked this frame.
+    pub fn extend_dirty(&mut self, rect : DirtyRect) {
+        self.dirty = self.dirty.union(&rect)
+    }
+
+    pub fn get_width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn get_height(&self) -> u32 {
+        self.height
+    }
+
+    /// Copy the latest (mutable) data within a rect, row by row.
+    pub fn copy_rect(&self, rect : &DirtyRect) -> Vec<PointData> {
+        let mut data = Vec::with_capacity((rect.width() * rect.height()) as usize);
+        for y in rect.min_y..rect.max_y {
+            let start = self.get_index(rect.min_x, y);
+            data.extend_from_slice(&self.mut_data[start..start + rect.width() as usize]);
+        }
+        data
+    }
+
+    /// Exchange the data within a rect with `data`, laid out as in copy_rect.
+    pub fn swap_rect(&mut self, rect : &DirtyRect, data : &mut [PointData]) {
+        let w = rect.width() as usize;
+        for (row, y) in (rect.min_y..rect.max_y).enumerate() {
+            let start = self.get_index(rect.min_x, y);
+            self.mut_data[start..start + w].swap_with_slice(&mut data[row * w..(row + 1) * w]);
+        }
+        self.extend_dirty(*rect);
+    }
+
     #[allow(dead_code)]
     pub fn get_dirty(&self) -> &DirtyRect {
         &self.dirty
@@ -147,15 +241,154 @@ impl PointDataStore {
         self.mut_data.clone()
     }
 
+    /// Rebuild as a width x height store, point (x, y) coming from `source(x, y)`
+    /// in the latest data.
+    fn remap<F : Fn(u32, u32) -> (u32, u32)>(&mut self, width : u32, height : u32, source : F) {
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = source(x, y);
+                data.push(self.mut_data[self.get_index(sx, sy)]);
+            }
+        }
+        self.replace(data, width, height);
+    }
+
     pub fn flip_hoz(&mut self) {
-        self.dirty = DirtyRect::all_dirty(self.width, self.height);
+        let (w, h) = (self.width, self.height);
+        self.remap(w, h, |x, y| (w - x - 1, y));
+    }
+
+    pub fn flip_vert(&mut self) {
+        let (w, h) = (self.width, self.height);
+        self.remap(w, h, |x, y| (x, h - y - 1));
+    }
+
+    /// Mirror across the diagonal from the top left, swapping width and height.
+    pub fn transpose(&mut self) {
+        let (w, h) = (self.width, self.height);
+        self.remap(h, w, |x, y| (y, x));
+    }
+
+    /// A quarter turn clockwise, swapping width and height.
+    pub fn rotate_90(&mut self) {
+        let (w, h) = (self.width, self.height);
+        self.remap(h, w, |x, y| (y, h - x - 1));
+    }
+
+    pub fn rotate_180(&mut self) {
+        let (w, h) = (self.width, self.height);
+        self.remap(w, h, |x, y| (w - x - 1, h - y - 1));
+    }
+
+    /// A quarter turn anticlockwise, swapping width and height.
+    pub fn rotate_270(&mut self) {
+        let (w, h) = (self.width, self.height);
+        self.remap(h, w, |x, y| (w - y - 1, x));
+    }
+
+    /// Change the size, keeping the points where they are relative to the old
+    /// top left, which lands at (offset_x, offset_y). New points come from `fill`.
+    pub fn resize<F : FnMut() -> PointData>(&mut self, width : u32, height : u32, offset_x : i32, offset_y : i32, mut fill : F) {
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let (sx, sy) = (x - offset_x, y - offset_y);
+                if sx >= 0 && sx < self.width as i32 && sy >= 0 && sy < self.height as i32 {
+                    data.push(self.mut_data[self.get_index(sx as u32, sy as u32)]);
+                }
+                else {
+                    data.push(fill());
+                }
+            }
+        }
+        self.replace(data, width, height);
+    }
+
+    /// Stretch to a new size, resampling bilinearly.
+    pub fn scale(&mut self, width : u32, height : u32) {
+        let (sx_scale, sy_scale) = (self.width as f32 / width as f32, self.height as f32 / height as f32);
+        let (max_x, max_y) = (self.width - 1, self.height - 1);
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            // Pixel centres line up, edges clamp.
+            let sy = ((y as f32 + 0.5) * sy_scale - 0.5).clamp(0.0, max_y as f32);
+            let (y0, fy) = (sy.floor() as u32, sy - sy.floor());
+            let y1 = (y0 + 1).min(max_y);
+            for x in 0..width {
+                let sx = ((x as f32 + 0.5) * sx_scale - 0.5).clamp(0.0, max_x as f32);
+                let (x0, fx) = (sx.floor() as u32, sx - sx.floor());
+                let x1 = (x0 + 1).min(max_x);
+
+                let at = |x, y| self.mut_data[self.get_index(x, y)];
+                let top = at(x0, y0).lerp(&at(x1, y0), fx);
+                let bottom = at(x0, y1).lerp(&at(x1, y1), fx);
+                data.push(top.lerp(&bottom, fy));
+            }
+        }
+        self.replace(data, width, height);
+    }
+
+    /// Turn `radians` clockwise about the centre, keeping the size, resampling
+    /// bilinearly. Corners turned in from outside the canvas are `fill`, or with
+    /// `wrap` come round from the opposite edge.
+    pub fn rotate(&mut self, radians : f32, wrap : bool, fill : PointData) {
+        let (w, h) = (self.width as i32, self.height as i32);
+        let (sin, cos) = radians.sin_cos();
+        let (cx, cy) = (self.width as f32 / 2.0, self.height as f32 / 2.0);
+        let point = |x : i32, y : i32| -> PointData {
+            if wrap {
+                self.mut_data[self.get_index(x.rem_euclid(w) as u32, y.rem_euclid(h) as u32)]
+            }
+            else if x < 0 || x >= w || y < 0 || y >= h {
+                fill
+            }
+            else {
+                self.mut_data[self.get_index(x as u32, y as u32)]
+            }
+        };
+
+        let mut data = Vec::with_capacity(self.mut_data.len());
+        for y in 0..h {
+            for x in 0..w {
+                // Back from the middle of the destination pixel to the source.
+                let (u, v) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                let sx = cos * u + sin * v + cx - 0.5;
+                let sy = cos * v - sin * u + cy - 0.5;
+                data.push(bilinear(sx, sy, point));
+            }
+        }
+        self.replace(data, self.width, self.height);
+    }
+
+    /// Move the points in `from` to `to`, point (x, y) of `to` coming from
+    /// `source(x, y)`, resampled bilinearly if `smooth` or else from the
+    /// nearest point. Points left behind in `from` are emptied.
+    pub fn move_selected<F : Fn(f32, f32) -> (f32, f32)>(&mut self, from : &Selection, to : &Selection, source : F, smooth : bool) {
+        let (max_x, max_y) = (self.width as i32 - 1, self.height as i32 - 1);
+        let point = |x : i32, y : i32| self.mut_data[self.get_index(x.clamp(0, max_x) as u32, y.clamp(0, max_y) as u32)];
+
+        let mut data = self.mut_data.clone();
         for y in 0..self.height {
             for x in 0..self.width {
                 let i = self.get_index(x, y);
-                let isrc = self.get_index(self.width - x - 1, y);
-                self.mut_data[i] = self.imm_data[isrc].clone();
+                if to.contains(x, y) {
+                    let (sx, sy) = source(x as f32, y as f32);
+                    data[i] = if smooth {
+                        bilinear(sx, sy, point)
+                    }
+                    else {
+                        point(sx.round() as i32, sy.round() as i32)
+                    };
+                }
+                else if from.contains(x, y) {
+                    data[i].thresh_band = 0.0;
+                }
             }
         }
+
+        self.mut_data = data;
+        self.extend_dirty(from.bounds().union(&to.bounds()));
     }
 
     /// Flush the mutable data and copy it into the immutable buffer.
@@ -179,4 +412,108 @@ impl PointDataStore {
         self.dirty_last = self.dirty;
         self.dirty = DirtyRect::clean_rect(self.width, self.height)
     }
+}
+
+/// Blend of the four points around (x, y), from `point`.
+fn bilinear<F : Fn(i32, i32) -> PointData>(x : f32, y : f32, point : F) -> PointData {
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (fx, fy) = (x - x.floor(), y - y.floor());
+    let top = point(x0, y0).lerp(&point(x0 + 1, y0), fx);
+    let bottom = point(x0, y0 + 1).lerp(&point(x0 + 1, y0 + 1), fx);
+    top.lerp(&bottom, fy)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::f32::consts::PI;
+
+  /// Each point's thresh_band is its index, so moves are easy to follow.
+  fn store(width : u32, height : u32) -> PointDataStore {
+    let data = (0..width * height).map(|i| PointData::new(i as f32, 0.0)).collect();
+    PointDataStore::new(data, width, height)
+  }
+
+  fn thresh(store : &PointDataStore) -> Vec<f32> {
+    store.get_clone().iter().map(|p| p.thresh_band).collect()
+  }
+
+  fn size(store : &PointDataStore) -> (u32, u32) {
+    (store.get_width(), store.get_height())
+  }
+
+  #[test]
+  fn flips_and_quarter_turns() {
+    // 0 1 2
+    // 3 4 5
+    let turned = |f : fn(&mut PointDataStore)| {
+      let mut s = store(3, 2);
+      f(&mut s);
+      (size(&s), thresh(&s))
+    };
+
+    assert_eq!(turned(PointDataStore::flip_hoz), ((3, 2), vec![2.0, 1.0, 0.0, 5.0, 4.0, 3.0]));
+    assert_eq!(turned(PointDataStore::flip_vert), ((3, 2), vec![3.0, 4.0, 5.0, 0.0, 1.0, 2.0]));
+    assert_eq!(turned(PointDataStore::transpose), ((2, 3), vec![0.0, 3.0, 1.0, 4.0, 2.0, 5.0]));
+    assert_eq!(turned(PointDataStore::rotate_90), ((2, 3), vec![3.0, 0.0, 4.0, 1.0, 5.0, 2.0]));
+    assert_eq!(turned(PointDataStore::rotate_180), ((3, 2), vec![5.0, 4.0, 3.0, 2.0, 1.0, 0.0]));
+    assert_eq!(turned(PointDataStore::rotate_270), ((2, 3), vec![2.0, 5.0, 1.0, 4.0, 0.0, 3.0]));
+  }
+
+  #[test]
+  fn rotate_by_a_quarter_matches_rotate_90() {
+    let mut by_radians = store(4, 4);
+    by_radians.rotate(PI / 2.0, false, PointData::new(-1.0, 0.0));
+    let mut quarter = store(4, 4);
+    quarter.rotate_90();
+
+    for (a, b) in thresh(&by_radians).iter().zip(thresh(&quarter)) {
+      assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+  }
+
+  #[test]
+  fn rotate_fills_or_wraps_corners() {
+    let fill = PointData::new(-1.0, 0.0);
+    let mut filled = store(8, 8);
+    filled.rotate(PI / 4.0, false, fill);
+    assert_eq!(size(&filled), (8, 8));
+    assert_eq!(filled.get(0, 0).thresh_band, -1.0);
+    // The middle only moves within the canvas.
+    assert!(filled.get(4, 4).thresh_band >= 0.0);
+
+    let mut wrapped = store(8, 8);
+    wrapped.rotate(PI / 4.0, true, fill);
+    assert!(wrapped.get(0, 0).thresh_band >= 0.0);
+
+    let mut unturned = store(8, 8);
+    unturned.rotate(0.0, false, fill);
+    assert_eq!(thresh(&unturned), thresh(&store(8, 8)));
+  }
+
+  #[test]
+  fn resize_keeps_points_and_fills_the_rest() {
+    // 0 1
+    // 2 3
+    let mut grown = store(2, 2);
+    grown.resize(3, 3, 1, 0, || PointData::new(-1.0, 0.0));
+    assert_eq!(size(&grown), (3, 3));
+    assert_eq!(thresh(&grown), vec![-1.0, 0.0, 1.0, -1.0, 2.0, 3.0, -1.0, -1.0, -1.0]);
+
+    let mut cropped = store(2, 2);
+    cropped.resize(1, 1, -1, -1, || panic!("nothing new to fill"));
+    assert_eq!(thresh(&cropped), vec![3.0]);
+  }
+
+  #[test]
+  fn scale_resamples() {
+    let mut up = store(2, 1);
+    up.scale(4, 2);
+    assert_eq!(size(&up), (4, 2));
+    assert_eq!(thresh(&up), vec![0.0, 0.25, 0.75, 1.0, 0.0, 0.25, 0.75, 1.0]);
+
+    let mut down = store(4, 1);
+    down.scale(2, 1);
+    assert_eq!(thresh(&down), vec![0.5, 2.5]);
+  }
 }
\ No newline at end of file
@@ -0,0 +1,53 @@
+use super::Color;
+
+/// Maps sampled points to palette indices.
+///
+/// color_band runs along `inks`, dithering between the two nearest so a
+/// band halfway between them comes out as an even mix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorRamp {
+  /// Never empty.
+  pub inks : Vec<u8>,
+  pub outline : u8,
+  pub background : u8,
+}
+
+impl Default for ColorRamp {
+  /// The original two inks, background and outline layout.
+  fn default() -> Self {
+    ColorRamp {
+      inks : vec![Color::X as u8, Color::Y as u8],
+      outline : Color::THRESH as u8,
+      background : Color::Z as u8,
+    }
+  }
+}
+
+impl ColorRamp {
+  /// Ink for `color_band`, `seed` in [0, 1) picks between neighbouring inks.
+  pub fn ink<F : FnOnce() -> f32>(&self, color_band : f32, seed : F) -> u8 {
+    if self.inks.len() == 1 {
+      return self.inks[0];
+    }
+
+    let last = self.inks.len() - 1;
+    let pos = color_band.clamp(0.0, 1.0) * last as f32;
+    let i = (pos.floor() as usize).min(last - 1);
+    if pos - (i as f32) > seed() {
+      self.inks[i + 1]
+    }
+    else {
+      self.inks[i]
+    }
+  }
+
+  /// Number of palette entries the ramp refers to.
+  pub fn palette_size(&self) -> usize {
+    let max = self.inks.iter()
+      .chain([self.outline, self.background].iter())
+      .max()
+      .copied()
+      .unwrap_or(0);
+    max as usize + 1
+  }
+}
@@ -0,0 +1,161 @@
+use wasm_bindgen::prelude::*;
+
+use super::pointdata::DirtyRect;
+
+/// How a new selection combines with the current one.
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SelectionOp {
+  #[default]
+  Replace,
+  Add,
+  Subtract,
+  Intersect,
+}
+
+/// Which points of the canvas edits are allowed to touch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+  width : u32,
+  height : u32,
+  mask : Vec<bool>,
+}
+
+impl Selection {
+  /// Points where `f(x, y)` is true.
+  pub fn from_fn<F : FnMut(u32, u32) -> bool>(width : u32, height : u32, mut f : F) -> Self {
+    let mut mask = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+      for x in 0..width {
+        mask.push(f(x, y));
+      }
+    }
+
+    Selection {
+      width : width,
+      height : height,
+      mask : mask,
+    }
+  }
+
+  /// Points whose centres lie within the rect between two corners, in pixels.
+  pub fn rect(width : u32, height : u32, x0 : f32, y0 : f32, x1 : f32, y1 : f32) -> Self {
+    let (min_x, max_x) = (x0.min(x1), x0.max(x1));
+    let (min_y, max_y) = (y0.min(y1), y0.max(y1));
+    Self::from_fn(width, height, |x, y| {
+      let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+      px >= min_x && px < max_x && py >= min_y && py < max_y
+    })
+  }
+
+  /// Points whose centres lie within the ellipse filling the rect between two corners.
+  pub fn ellipse(width : u32, height : u32, x0 : f32, y0 : f32, x1 : f32, y1 : f32) -> Self {
+    let (cx, cy) = ((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+    let (rx, ry) = ((x1 - x0).abs() / 2.0, (y1 - y0).abs() / 2.0);
+    if rx == 0.0 || ry == 0.0 {
+      return Self::from_fn(width, height, |_, _| false);
+    }
+
+    Self::from_fn(width, height, |x, y| {
+      let (u, v) = ((x as f32 + 0.5 - cx) / rx, (y as f32 + 0.5 - cy) / ry);
+      u * u + v * v <= 1.0
+    })
+  }
+
+  /// Points whose centres lie within a closed polygon, in pixels.
+  /// Self intersecting outlines use the even odd rule.
+  pub fn lasso(width : u32, height : u32, points : &[(f32, f32)]) -> Self {
+    Self::from_fn(width, height, |x, y| {
+      let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+      let mut inside = false;
+      for (i, &(ax, ay)) in points.iter().enumerate() {
+        let (bx, by) = points[(i + 1) % points.len()];
+        if (ay > py) != (by > py) && px < ax + (py - ay) / (by - ay) * (bx - ax) {
+          inside = !inside;
+        }
+      }
+      inside
+    })
+  }
+
+  /// Points connected to (x, y) through neighbours where `matches` holds,
+  /// across the edges too when wrapping. Empty if (x, y) itself doesn't match.
+  pub fn flood<F : Fn(u32, u32) -> bool>(width : u32, height : u32, x : u32, y : u32, wrap : bool, matches : F) -> Self {
+    let mut selection = Self::from_fn(width, height, |_, _| false);
+    if x >= width || y >= height || !matches(x, y) {
+      return selection;
+    }
+
+    let (w, h) = (width as i32, height as i32);
+    let mut stack = vec![(x as i32, y as i32)];
+    selection.mask[(y * width + x) as usize] = true;
+    while let Some((x, y)) = stack.pop() {
+      for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+        let (mut nx, mut ny) = (x + dx, y + dy);
+        if wrap {
+          nx = nx.rem_euclid(w);
+          ny = ny.rem_euclid(h);
+        }
+        else if nx < 0 || nx >= w || ny < 0 || ny >= h {
+          continue;
+        }
+
+        let i = (ny * w + nx) as usize;
+        if !selection.mask[i] && matches(nx as u32, ny as u32) {
+          selection.mask[i] = true;
+          stack.push((nx, ny));
+        }
+      }
+    }
+    selection
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  pub fn contains(&self, x : u32, y : u32) -> bool {
+    x < self.width && y < self.height && self.mask[(y * self.width + x) as usize]
+  }
+
+  /// Smallest rect holding every selected point.
+  pub fn bounds(&self) -> DirtyRect {
+    let mut rect = DirtyRect::default();
+    for y in 0..self.height {
+      for x in 0..self.width {
+        if self.contains(x, y) {
+          rect = rect.union(&DirtyRect {
+            min_x : x,
+            min_y : y,
+            max_x : x + 1,
+            max_y : y + 1,
+          });
+        }
+      }
+    }
+    rect
+  }
+
+  pub fn invert(&mut self) {
+    for m in self.mask.iter_mut() {
+      *m = !*m;
+    }
+  }
+
+  /// Combine `other`, the same size, into this selection.
+  pub fn combine(&mut self, other : &Selection, op : SelectionOp) {
+    for (m, &o) in self.mask.iter_mut().zip(other.mask.iter()) {
+      *m = match op {
+        SelectionOp::Replace => o,
+        SelectionOp::Add => *m || o,
+        SelectionOp::Subtract => *m && !o,
+        SelectionOp::Intersect => *m && o,
+      };
+    }
+  }
+}
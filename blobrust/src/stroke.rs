@@ -0,0 +1,84 @@
+use super::brush::StampInput;
+use super::utils::{sqr, clamp_unit};
+
+/// Distance between interpolated stamps, as a fraction of the brush size.
+pub const STROKE_SPACING : f32 = 0.25;
+
+/// Moving this many brush sizes between samples counts as full velocity.
+pub const FULL_VELOCITY_SIZES : f32 = 4.0;
+
+/// A stroke in progress.
+///
+/// Input only arrives once per frame so a fast stroke jumps a long way between
+/// samples. We walk the path between samples laying stamps at an even spacing,
+/// carrying any leftover distance on to the next sample. Pen input is blended
+/// along the way so pressure tapers smoothly.
+pub struct Stroke {
+  // Last stamp position in pixels, and the pen input it was stamped with.
+  x : f32,
+  y : f32,
+  input : StampInput,
+  // Last sample position, for velocity.
+  sample_x : f32,
+  sample_y : f32,
+  remove : bool,
+}
+
+impl Stroke {
+  pub fn new(x : f32, y : f32, input : StampInput, remove : bool) -> Self {
+    Stroke {
+      x : x,
+      y : y,
+      input : input,
+      sample_x : x,
+      sample_y : y,
+      remove : remove,
+    }
+  }
+
+  pub fn is_remove(&self) -> bool {
+    self.remove
+  }
+
+  /// Stamps on the way to (x, y), `spacing` pixels apart.
+  /// `input.velocity` is ignored and worked out from how far we moved relative
+  /// to `size`.
+  pub fn advance(&mut self, x : f32, y : f32, input : StampInput, spacing : f32, size : f32) -> Vec<(f32, f32, StampInput)> {
+    let moved = (sqr(x - self.sample_x) + sqr(y - self.sample_y)).sqrt();
+    let input = StampInput {
+      velocity : clamp_unit(moved / (size * FULL_VELOCITY_SIZES).max(1.0)),
+      ..input
+    };
+    self.sample_x = x;
+    self.sample_y = y;
+
+    let (dx, dy) = (x - self.x, y - self.y);
+    let dist = (sqr(dx) + sqr(dy)).sqrt();
+    let steps = (dist / spacing).floor() as u32;
+    if steps == 0 {
+      return Vec::new();
+    }
+
+    let (step_x, step_y) = (dx / dist * spacing, dy / dist * spacing);
+    let stamps : Vec<(f32, f32, StampInput)> = (1..=steps)
+      .map(|i| {
+        let k = i as f32 * spacing / dist;
+        (self.x + step_x * i as f32, self.y + step_y * i as f32, lerp_input(&self.input, &input, k))
+      })
+      .collect();
+
+    let (last_x, last_y, last_input) = stamps[stamps.len() - 1];
+    self.x = last_x;
+    self.y = last_y;
+    self.input = last_input;
+    stamps
+  }
+}
+
+fn lerp_input(a : &StampInput, b : &StampInput, k : f32) -> StampInput {
+  StampInput {
+    pressure : a.pressure + (b.pressure - a.pressure) * k,
+    tilt : a.tilt + (b.tilt - a.tilt) * k,
+    velocity : a.velocity + (b.velocity - a.velocity) * k,
+  }
+}
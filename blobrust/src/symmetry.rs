@@ -0,0 +1,92 @@
+use std::f32::consts::TAU;
+
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SymmetryMode {
+  #[default]
+  Off,
+  /// Mirrored left to right, like flip_hoz.
+  Horizontal,
+  /// Mirrored top to bottom.
+  Vertical,
+  Both,
+  /// Repeated `folds` times around the centre.
+  Radial,
+}
+
+/// 2x2 matrix, row major, taking a brush direction to the direction in an image.
+pub type Transform = [f32; 4];
+
+pub const IDENTITY : Transform = [1.0, 0.0, 0.0, 1.0];
+
+/// Live symmetry applied to every stamp.
+#[derive(Clone, Copy, Debug)]
+pub struct Symmetry {
+  pub mode : SymmetryMode,
+  pub folds : u32,
+  /// Normalized centre the axes pass through.
+  pub centre_x : f32,
+  pub centre_y : f32,
+}
+
+impl Default for Symmetry {
+  fn default() -> Self {
+    Symmetry {
+      mode : SymmetryMode::Off,
+      folds : 6,
+      centre_x : 0.5,
+      centre_y : 0.5,
+    }
+  }
+}
+
+impl Symmetry {
+  /// Every pixel a stamp at (px, py) should be repeated at, starting with
+  /// (px, py) itself, along with the transform for that image.
+  /// Images landing on the same pixel, say on an axis, are only returned once
+  /// so the stamp isn't applied twice.
+  pub fn images(&self, px : i32, py : i32, width : u32, height : u32) -> Vec<(i32, i32, Transform)> {
+    // In pixel indices, so mirroring about the middle of the canvas maps the
+    // first column exactly onto the last.
+    let (cx, cy) = (self.centre_x * width as f32 - 0.5, self.centre_y * height as f32 - 0.5);
+    let (x, y) = (px as f32, py as f32);
+    let mirror_x = (2.0 * cx - x).round() as i32;
+    let mirror_y = (2.0 * cy - y).round() as i32;
+
+    let mut images = vec![(px, py, IDENTITY)];
+    match self.mode {
+      SymmetryMode::Off => {},
+      SymmetryMode::Horizontal => {
+        images.push((mirror_x, py, [-1.0, 0.0, 0.0, 1.0]));
+      },
+      SymmetryMode::Vertical => {
+        images.push((px, mirror_y, [1.0, 0.0, 0.0, -1.0]));
+      },
+      SymmetryMode::Both => {
+        images.push((mirror_x, py, [-1.0, 0.0, 0.0, 1.0]));
+        images.push((px, mirror_y, [1.0, 0.0, 0.0, -1.0]));
+        images.push((mirror_x, mirror_y, [-1.0, 0.0, 0.0, -1.0]));
+      },
+      SymmetryMode::Radial => {
+        let (dx, dy) = (x - cx, y - cy);
+        for i in 1..self.folds.max(1) {
+          let (sin, cos) = (TAU * i as f32 / self.folds as f32).sin_cos();
+          let rx = cx + dx * cos - dy * sin;
+          let ry = cy + dx * sin + dy * cos;
+          images.push((rx.round() as i32, ry.round() as i32, [cos, -sin, sin, cos]));
+        }
+      },
+    }
+
+    let mut unique : Vec<(i32, i32, Transform)> = Vec::with_capacity(images.len());
+    for image in images {
+      if !unique.iter().any(|u| u.0 == image.0 && u.1 == image.1) {
+        unique.push(image);
+      }
+    }
+    unique
+  }
+}
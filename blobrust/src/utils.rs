@@ -1,4 +1,5 @@
-use super::rand_core::RngCore;
+use super::rand_core::{RngCore, SeedableRng};
+use super::rand_xorshift::XorShiftRng;
 
 pub fn sqr(x : f32) -> f32 {
   x*x
@@ -21,14 +22,39 @@ pub fn clamp_unit(x : f32) -> f32 {
 }
 
 pub fn rand_unit(rng: &mut dyn RngCore) -> f32{
-  (rng.next_u32() as f32) / (std::u32::MAX as f32)
+  (rng.next_u32() as f32) / (u32::MAX as f32)
 }
 
 pub fn lerpk(x0: f32, x1: f32, k: f32) -> f32 {
   (x0 * k + x1) / (k + 1.0)
 }
 
+/// Into [0, 1).
+pub fn wrap_unit(x : f32) -> f32 {
+  x - x.floor()
+}
+
+/// Into [-0.5, 0.5), the shortest way round between two phases.
+pub fn wrap_half(x : f32) -> f32 {
+  wrap_unit(x + 0.5) - 0.5
+}
+
 pub fn normalize(x : f32, y : f32) -> (f32, f32) {
   let mag = (sqr(x) + sqr(y)).sqrt();
   (x / mag, y / mag)
+}
+
+/// Independent random streams derived from a canvas seed.
+/// Each gets its own generator so drawing from one never shifts the others.
+#[derive(Clone, Copy)]
+pub enum RngStream {
+  Noise,
+  Dither,
+  Position,
+}
+
+pub fn stream_rng(seed : u64, stream : RngStream) -> XorShiftRng {
+  // Spread the stream index over the seed so neighbouring seeds don't share streams.
+  let k = (stream as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+  XorShiftRng::seed_from_u64(seed ^ k)
 }
\ No newline at end of file
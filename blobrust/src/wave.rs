@@ -0,0 +1,142 @@
+use std::f32::consts::{PI, TAU};
+
+use wasm_bindgen::prelude::*;
+
+use super::utils::sqr;
+
+/// Shape of one cycle of the threshold wave.
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Waveform {
+  #[default]
+  Sine,
+  Triangle,
+  /// Smooth random bumps, the same every cycle so loops stay seamless.
+  Noise,
+}
+
+impl Waveform {
+  pub fn from_u8(x : u8) -> Option<Self> {
+    match x {
+      0 => Some(Waveform::Sine),
+      1 => Some(Waveform::Triangle),
+      2 => Some(Waveform::Noise),
+      _ => None,
+    }
+  }
+}
+
+/// Random values per cycle for the noise waveform.
+const NOISE_KNOTS : u32 = 8;
+
+/// How the threshold wave travels across the canvas.
+#[derive(Clone, Copy, Debug)]
+pub struct Wave {
+  /// Direction the wave varies in, radians from the x axis.
+  pub direction : f32,
+  /// Cycles across the canvas.
+  pub frequency : f32,
+  /// Ripple out from the centre rather than travel in a direction.
+  pub radial : bool,
+  /// Normalized centre for radial waves.
+  pub centre_x : f32,
+  pub centre_y : f32,
+  pub waveform : Waveform,
+}
+
+impl Default for Wave {
+  /// Rolls vertically once over the height of the canvas.
+  fn default() -> Self {
+    Wave {
+      direction : PI / 2.0,
+      frequency : 1.0,
+      radial : false,
+      centre_x : 0.5,
+      centre_y : 0.5,
+      waveform : Waveform::Sine,
+    }
+  }
+}
+
+impl Wave {
+  /// Angle of the wave at a point, before any per point wobble.
+  /// `phase` is the fraction of the way through the cycle.
+  /// With `wrap` the wave matches up across opposite edges of the canvas.
+  pub fn angle(&self, phase : f64, x : u32, y : u32, width : u32, height : u32, wrap : bool) -> f32 {
+    let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+    let t = TAU * phase as f32;
+
+    if self.radial {
+      let (mut du, mut dv) = (u - self.centre_x, v - self.centre_y);
+      if wrap {
+        // Distance to the nearest copy of the centre on the tiled plane.
+        du -= du.round();
+        dv -= dv.round();
+      }
+      // Scaled by height so rings stay round on a wide canvas.
+      let aspect = width as f32 / height as f32;
+      let dist = (sqr(du * aspect) + sqr(dv)).sqrt();
+      t - TAU * self.frequency * dist
+    }
+    else {
+      let (mut kx, mut ky) = (self.frequency * self.direction.cos(), self.frequency * self.direction.sin());
+      if wrap {
+        (kx, ky) = tiling_cycles(kx, ky);
+      }
+      t + TAU * (u * kx + v * ky)
+    }
+  }
+
+  /// Value of the waveform at `angle`, in [-1, 1].
+  pub fn shape(&self, angle : f32) -> f32 {
+    let cycle = angle / TAU;
+    let cycle = cycle - cycle.floor();
+
+    match self.waveform {
+      Waveform::Sine => angle.sin(),
+      Waveform::Triangle => {
+        // Lined up with sine, peaking at a quarter cycle.
+        let c = cycle + 0.25;
+        1.0 - 4.0 * (c - c.floor() - 0.5).abs()
+      },
+      Waveform::Noise => {
+        let pos = cycle * NOISE_KNOTS as f32;
+        let i = pos.floor() as u32;
+        let k = pos - pos.floor();
+        let k = k * k * (3.0 - 2.0 * k);
+        let a = knot(i % NOISE_KNOTS);
+        let b = knot((i + 1) % NOISE_KNOTS);
+        a + (b - a) * k
+      },
+    }
+  }
+}
+
+/// Nearest whole number of cycles across and down the canvas, so the wave
+/// starts and ends each tile on the same phase. Keeps at least one cycle so
+/// a low frequency doesn't stop the wave travelling.
+fn tiling_cycles(kx : f32, ky : f32) -> (f32, f32) {
+  let (rx, ry) = (kx.round(), ky.round());
+  if rx != 0.0 || ry != 0.0 {
+    (rx, ry)
+  }
+  else if kx.abs() > ky.abs() {
+    (kx.signum(), 0.0)
+  }
+  else if ky != 0.0 {
+    (0.0, ky.signum())
+  }
+  else {
+    (0.0, 0.0)
+  }
+}
+
+/// Fixed pseudo random value in [-1, 1] for a noise knot.
+fn knot(i : u32) -> f32 {
+  let mut h = i.wrapping_mul(0x9E37_79B9) ^ 0x85EB_CA6B;
+  h ^= h >> 15;
+  h = h.wrapping_mul(0x2C1B_3C6D);
+  h ^= h >> 12;
+  (h as f32 / u32::MAX as f32) * 2.0 - 1.0
+}
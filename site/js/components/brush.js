@@ -4,8 +4,17 @@ let brushPaintbrush = Brush.new_inv(38, 1.2, 0.4);
 let brushOutliner = Brush.new_outliner(8);
 let brushSmudger = Brush.new_smudger(64);
 let brushColorer = Brush.new_colorer(32, 0.5);
+let brushWobbler = Brush.new_wobbler(32, 0.0, 0.0);
+let brushLineWeight = Brush.new_line_weight(16, 2.0);
 let brush = brushPaintbrush;
 
+// Taper with pen pressure, no effect for mice.
+brushPaintbrush.set_pressure_size(1.0);
+brushOutliner.set_pressure_size(1.0);
+brushColorer.set_pressure_strength(1.0);
+brushWobbler.set_pressure_strength(1.0);
+brushLineWeight.set_pressure_strength(1.0);
+
 export function SetSize(brush, size) {
   switch (brush) {
     case "paintbrush":
@@ -20,6 +29,12 @@ export function SetSize(brush, size) {
     case "colorer":
       brushColorer.set_size(size);
       break;
+    case "wobbler":
+      brushWobbler.set_size(size);
+      break;
+    case "lineweight":
+      brushLineWeight.set_size(size);
+      break;
   }
 }
 
@@ -43,6 +58,14 @@ export function ResetColorer() {
   brush = brushColorer;
 }
 
+export function ResetWobbler() {
+  brush = brushWobbler;
+}
+
+export function ResetLineWeight() {
+  brush = brushLineWeight;
+}
+
 export function GetBrush(name) {
   if (name) {
   switch (name) {
@@ -54,6 +77,10 @@ export function GetBrush(name) {
       return brushSmudger;
     case "colorer":
       return brushColorer;
+    case "wobbler":
+      return brushWobbler;
+    case "lineweight":
+      return brushLineWeight;
     }
   }
 
@@ -1,13 +1,13 @@
 import { GetBrush, RecordMousePos } from "./brush.js";
-import {BlobCanvas} from "blobrust";
+import {BlobCanvas, SelectionOp, FillBands, ImportSettings} from "blobrust";
 import * as wasm from "../../node_modules/blobrust/blobrust_bg.wasm";
-import GIF from 'gif.js';
-import {GetPalette, GetPaletteForGif} from './palette.js';
+import {GetPalette, GetBlobPalette, AddPalette} from './palette.js';
 import {GetBrushTab} from './ui.js';
 
 const w = 256;
 const h = 200;
 var blobCanvas = BlobCanvas.new(w, h);
+ApplyPalette();
 
 let canvas = document.getElementById('canvas');
 canvas.oncontextmenu = () => false;
@@ -18,7 +18,12 @@ ctx.imageSmoothingEnabled = false;
 let mouseX;
 let mouseY;
 
+// Only pens report meaningful pressure, mice and touch paint at full strength.
+let penPressure = 1;
+let penTilt = 0;
+
 let painting = false;
+let stroking = false;
 let right_mouse_button = false;
 
 let prev = 0;
@@ -27,108 +32,175 @@ let fps_k = 20;
 
 let t = 0;
 
+// Dragging makes a selection rather than painting while a select tool is picked.
+// One of "rect", "ellipse", "lasso", "wand" or "fill", null to paint.
+let selectTool = null;
+let selectOp = SelectionOp.Replace;
+let selectStart = null;
+let lassoPoints = [];
+
 let gifconfig = {
-    gif: null,
-    rendering: false,
     blob: null,
-    framecount: 0,
 }
 
 export function StartCapture(progressCallback, resetCallback, downloadLinkCallback) {
-  let gpalette = GetPaletteForGif();
-  gifconfig.gif = new GIF({
-    workers: 4,
-    globalPalette: gpalette,
-    quality: 4,
-  });
-    
   progressCallback("Capturing Frames...")
 
-  gifconfig.gif.on('finished', function(blob) {
+  // Let the progress text paint before we block on encoding.
+  window.setTimeout(() => {
+    // Renders exactly one threshold cycle at fixed phases, so the gif loops cleanly.
+    const bytes = blobCanvas.encode_gif(true);
+
+    const blob = new Blob([bytes], {type: "image/gif"});
     gifconfig.blob = blob;
     console.log("Finished rendering - " + blob.size);
     let url = URL.createObjectURL(blob);
-    gifconfig.gif = null;
-    gifconfig.rendering = false;
     progressCallback("View Capture");
     resetCallback(true);
     downloadLinkCallback(url)
-  });
-
-  gifconfig.gif.on('progress', function(p) {
-      let progress = "Rendering " + Math.floor(p*100) + "%...";
-      console.log(progress);
-      progressCallback(progress);
-  })
-
-  const tt = 1 / (1000 * 1000 * blobCanvas.get_thresh_t_mult());
-  gifconfig.framecount = tt * ((2 * 3.141) * 50);
-  console.log("Recording gif with frames: " + gifconfig.framecount);
+  }, 0);
 }
 
 export function ResetCapture() {
-    gifconfig.gif = null;
-    gifconfig.rendering = false;
     gifconfig.blob = null;
 }
 
-function updateGif() {
-  if (gifconfig.gif) {
-      if (gifconfig.gif.frames.length > gifconfig.framecount) {
-          if (!gifconfig.rendering) {
-              gifconfig.gif.render();
-              gifconfig.rendering = true;
-          }
-      }
-      else {
-        const delayMs = 20;
-        gifconfig.gif.addFrame(canvas, {delay: delayMs, copy: true});
-      }
-  }
-}
-
 export function Tick(timestep) {
     const dt_ms = timestep - prev;
     prev = timestep;
 
-    if (painting) {
-      if (mouseX > 0 && mouseX < 1 && mouseY > 0 && mouseY < 1 ) {
-        let brush = GetBrush();
-        if (brush) {
-          if (!right_mouse_button) {
-            blobCanvas.apply_brush(mouseX, mouseY, brush);
-          }
-          else {
-            blobCanvas.remove_brush(mouseX, mouseY, brush);
-          }
+    if (blobCanvas.is_pasting()) {
+      // Drag the floating paste around until it is committed.
+      if (painting) {
+        blobCanvas.set_paste_position(mouseX, mouseY);
+      }
+    }
+    else if (selectTool) {
+      TickSelect();
+    }
+    else if (painting) {
+      let brush = GetBrush();
+      if (brush) {
+        if (stroking) {
+          // Interpolates from the last position, so keep following the pointer
+          // even off the edge of the canvas.
+          blobCanvas.stroke_to_with_pressure(mouseX, mouseY, brush, penPressure, penTilt);
+        }
+        else if (mouseX > 0 && mouseX < 1 && mouseY > 0 && mouseY < 1 ) {
+          blobCanvas.begin_stroke_with_pressure(mouseX, mouseY, brush, right_mouse_button, penPressure, penTilt);
+          stroking = true;
         }
       }
     }
+    else if (stroking) {
+      blobCanvas.end_stroke();
+      stroking = false;
+    }
 
     RecordMousePos(mouseX, mouseY);
 
     t+=1;
-    let framerate = fps_avg;
-    if (gifconfig.gif != null)
-    {
-      // Record at 50 fps
-      framerate = 50;
-    }
-
-    blobCanvas.tick((1000 * 1000) / framerate);
+    blobCanvas.tick((1000 * 1000) / fps_avg);
 
     Draw(dt_ms);
-    
-    updateGif();
+
     window.requestAnimationFrame(Tick);
 }
 
+function TickSelect() {
+  if (selectTool == "fill") {
+    // Once per click, the right button empties rather than fills.
+    if (painting && !selectStart) {
+      selectStart = [mouseX, mouseY];
+      blobCanvas.fill(mouseX, mouseY, right_mouse_button ? 0 : 1, 0, FillBands.Thresh);
+    }
+    else if (!painting) {
+      selectStart = null;
+    }
+    return;
+  }
+
+  if (painting) {
+    if (!selectStart) {
+      selectStart = [mouseX, mouseY];
+      lassoPoints = [];
+    }
+    lassoPoints.push(mouseX, mouseY);
+    return;
+  }
+
+  if (!selectStart) {
+    return;
+  }
+
+  const [x0, y0] = selectStart;
+  selectStart = null;
+  // A click without dragging drops the selection.
+  const dragged = x0 != mouseX || y0 != mouseY;
+  switch (selectTool) {
+    case "rect":
+      return dragged ? blobCanvas.select_rect(x0, y0, mouseX, mouseY, selectOp) : blobCanvas.select_all();
+    case "ellipse":
+      return dragged ? blobCanvas.select_ellipse(x0, y0, mouseX, mouseY, selectOp) : blobCanvas.select_all();
+    case "lasso":
+      return dragged ? blobCanvas.select_lasso(new Float32Array(lassoPoints), selectOp) : blobCanvas.select_all();
+    case "wand":
+      return blobCanvas.select_by_threshold(mouseX, mouseY, 0.15, selectOp);
+  }
+}
+
+export function SetSelectTool(tool) {
+  selectTool = tool;
+  selectStart = null;
+}
+
+export function SetSelectOp(op) {
+  selectOp = op;
+}
+
+export function SelectAll() {
+  blobCanvas.select_all();
+}
+
+export function InvertSelection() {
+  blobCanvas.invert_selection();
+}
+
+export function Copy() {
+  blobCanvas.copy();
+}
+
+export function Cut() {
+  blobCanvas.cut();
+}
+
+export function Paste() {
+  blobCanvas.paste();
+}
+
+export function FlipPaste() {
+  blobCanvas.flip_paste_hoz();
+}
+
+export function CommitPaste() {
+  blobCanvas.commit_paste();
+}
+
+export function CancelPaste() {
+  blobCanvas.cancel_paste();
+}
+
 function Draw(dt_ms) {
+    SyncCanvasSize();
     const cols = GetPalette();
     const drawBufSize = blobCanvas.get_draw_buffer_size();
     blobCanvas.fill_draw_buffer();
-    const drawBufPtr = blobCanvas.get_draw_buffer();
-    const drawBuf = new Uint8Array(wasm.memory.buffer, drawBufPtr, drawBufSize * 3);
+    // Canvases larger than 256x256 use u16 coordinates.
+    // Each wide point is [x, y, col] u16s.
+    const wide = blobCanvas.is_draw_buffer_wide();
+    const drawBuf = wide
+      ? new Uint16Array(wasm.memory.buffer, blobCanvas.get_wide_draw_buffer(), drawBufSize * 3)
+      : new Uint8Array(wasm.memory.buffer, blobCanvas.get_draw_buffer(), drawBufSize * 3);
 
     let i = 0;
     while (i < drawBufSize * 3) {
@@ -161,9 +233,42 @@ export function SetBlobCanvasThreshTVar(x) {
 export function SetBlobCanvasThreshTMult(x) {
   blobCanvas.set_thresh_t_mult(x);
 }
+export function SetBlobCanvasOutlineWidth(x) {
+  blobCanvas.set_outline_width(x);
+}
+export function SetBlobCanvasSymmetry(mode) {
+  blobCanvas.set_symmetry(mode);
+}
+export function SetBlobCanvasSymmetryFolds(folds) {
+  blobCanvas.set_symmetry_folds(folds);
+}
+export function SetBlobCanvasWrap(wrap) {
+  blobCanvas.set_wrap(wrap);
+}
+export function SetBlobCanvasWaveDirection(radians) {
+  blobCanvas.set_wave_direction(radians);
+}
+export function SetBlobCanvasWaveFrequency(cycles) {
+  blobCanvas.set_wave_frequency(cycles);
+}
+export function SetBlobCanvasWaveRadial(radial) {
+  blobCanvas.set_wave_radial(radial);
+}
+export function SetBlobCanvasWaveform(waveform) {
+  blobCanvas.set_waveform(waveform);
+}
+// Hand the current palette to the canvas, for exports and saving.
+export function ApplyPalette() {
+  const palette = GetBlobPalette();
+  blobCanvas.set_palette(palette);
+  palette.free();
+}
 export function Undo() {
   blobCanvas.try_pop_undo();
 }
+export function Redo() {
+  blobCanvas.try_pop_redo();
+}
 export function ClearCanvas() {
   blobCanvas.clear();
 }
@@ -172,15 +277,130 @@ export function FlipCanvas() {
   blobCanvas.flip_hoz();
 }
 
+export function FlipCanvasVertical() {
+  blobCanvas.flip_vert();
+}
+
+// Quarter turns swap width and height, Draw resizes the page canvas to match.
+export function RotateCanvas() {
+  blobCanvas.rotate_90();
+}
+
+// Download the canvas as a .blob document, which blobpaint can render headless.
+export function SaveCanvas() {
+  const data = new Blob([blobCanvas.serialize()], {type: "application/octet-stream"});
+  const link = document.createElement('a');
+  link.href = URL.createObjectURL(data);
+  link.download = "painting.blob";
+  link.click();
+  setTimeout(() => URL.revokeObjectURL(link.href), 0);
+}
+
+// Replace the canvas with a saved document, resolves to an error message or "".
+export function LoadCanvas(file) {
+  return file.arrayBuffer().then((buffer) => {
+    try {
+      const loaded = BlobCanvas.deserialize(new Uint8Array(buffer));
+      blobCanvas.free();
+      blobCanvas = loaded;
+      // Switch to the palette saved with it, so the site draws what was saved.
+      const palette = blobCanvas.get_palette();
+      AddPalette(palette);
+      palette.free();
+      return "";
+    }
+    catch (e) {
+      return String(e);
+    }
+  });
+}
+
+export function GetCanvasSize() {
+  return [blobCanvas.width, blobCanvas.height];
+}
+
+// Anchored in the middle, new space is filled with noise.
+export function ResizeCanvas(width, height) {
+  return blobCanvas.resize_canvas(width, height, 0.5, 0.5);
+}
+
+export function ScaleCanvas(width, height) {
+  return blobCanvas.scale_canvas(width, height);
+}
+
+// Trace a picture into a new canvas, shrunk to fit the current one.
+// Dark lines on a light background become blobs, unless lightOnDark.
+// Resolves to an error message, empty on success.
+export function ImportImage(file, lightOnDark) {
+  return new Promise((resolve) => {
+    const img = new Image();
+    img.onload = () => {
+      const scale = Math.min(1, blobCanvas.width / img.width, blobCanvas.height / img.height);
+      const width = Math.max(1, Math.round(img.width * scale));
+      const height = Math.max(1, Math.round(img.height * scale));
+
+      const scratch = document.createElement('canvas');
+      scratch.width = width;
+      scratch.height = height;
+      const scratchCtx = scratch.getContext('2d');
+      scratchCtx.drawImage(img, 0, 0, width, height);
+      const pixels = scratchCtx.getImageData(0, 0, width, height);
+      URL.revokeObjectURL(img.src);
+
+      const settings = ImportSettings.new();
+      settings.invert = !lightOnDark;
+      try {
+        const imported = BlobCanvas.from_rgba(new Uint8Array(pixels.data.buffer), width, height, settings);
+        blobCanvas.free();
+        blobCanvas = imported;
+        ApplyPalette();
+        resolve("");
+      }
+      catch (e) {
+        resolve(String(e));
+      }
+      settings.free();
+    };
+    img.onerror = () => resolve("Couldn't read " + file.name);
+    img.src = URL.createObjectURL(file);
+  });
+}
+
+// Match the page canvas to the blob canvas after anything that changes its size.
+// Setting the size clears it, the draw buffer repaints it over the next frames.
+function SyncCanvasSize() {
+  const width = blobCanvas.width;
+  const height = blobCanvas.height;
+  if (canvas.width != width || canvas.height != height) {
+    canvas.width = width;
+    canvas.height = height;
+    ctx.imageSmoothingEnabled = false;
+  }
+}
+
 canvas.addEventListener('mousemove', event => {
     let rect = canvas.getBoundingClientRect();
     mouseX = (event.clientX - rect.left) / rect.width;
     mouseY = (event.clientY - rect.top) / rect.height;
 });
 
+function recordPen(event) {
+  if (event.pointerType === "pen") {
+    penPressure = event.pressure;
+    // tiltX / tiltY are -90 to 90 degrees from upright.
+    penTilt = Math.min(1, Math.hypot(event.tiltX, event.tiltY) / 90);
+  }
+  else {
+    penPressure = 1;
+    penTilt = 0;
+  }
+}
+
+canvas.addEventListener('pointerdown', recordPen);
+canvas.addEventListener('pointermove', recordPen);
+
 canvas.addEventListener('mousedown', event => {
     event.preventDefault();
-    blobCanvas.push_undo();
     painting = true;
     right_mouse_button = (event.buttons & 0x2) != 0;
 });
@@ -191,8 +411,45 @@ window.addEventListener('mouseup', event => {
 });
 
 window.addEventListener('keydown', event => {
+  // Leave typing in text fields alone.
+  const tag = event.target.tagName;
+  if (tag === "INPUT" || tag === "TEXTAREA") {
+    return;
+  }
+
+  let handled = true;
   if ((event.code === "KeyZ" || event.keyCode === 90) && event.ctrlKey) {
-    Undo();
+    if (event.shiftKey) {
+      Redo();
+    }
+    else {
+      Undo();
+    }
+  }
+  else if ((event.code === "KeyY" || event.keyCode === 89) && event.ctrlKey) {
+    Redo();
+  }
+  else if (event.code === "KeyC" && event.ctrlKey) {
+    Copy();
+  }
+  else if (event.code === "KeyX" && event.ctrlKey) {
+    Cut();
+  }
+  else if (event.code === "KeyV" && event.ctrlKey) {
+    Paste();
+  }
+  else if (event.code === "Enter" && blobCanvas.is_pasting()) {
+    CommitPaste();
+  }
+  else if (event.code === "Escape" && blobCanvas.is_pasting()) {
+    CancelPaste();
+  }
+  else {
+    handled = false;
+  }
+
+  if (handled) {
+    event.preventDefault();
   }
 });
 
@@ -216,7 +473,6 @@ canvas.addEventListener("touchstart", event => {
     event.preventDefault();
   }
 
-  blobCanvas.push_undo();
   painting = true;
   setMousePosFromTouch();
 });
@@ -1,3 +1,4 @@
+import { Palette } from "../../node_modules/blobrust/blobrust.js"
 
 const palettes = [
     {
@@ -105,24 +106,25 @@ export function PrevPalette() {
         current = palettes.length - 1;
     }
 }
-export function GetPaletteForGif() {
-    // Export to format expecte by gif.js
-    // Array of triples [r0, g0, b0, r1, g1, b1, ...]
-    // Eg
-    //[0xFF, 0xFF, 0x88, 0xFF, 0xAA, 0x88, 0xAA, 0x88, 0x44, 0x00, 0x00, 0x00]
-    let cols = [];
-    let p = palettes[current];
-    for (let i = 0; i < 4; i++) {
-        const hexstring = p.data[i];
 
-        // bit hacky
-        const red = parseInt("0x" + hexstring.substring(1, 3));
-        const green = parseInt("0x" + hexstring.substring(3, 5));
-        const blue = parseInt("0x" + hexstring.substring(5, 7));
-        cols.push(red);
-        cols.push(green);
-        cols.push(blue);
+// The same colours as a blobrust Palette, which the canvas renders and exports with.
+// The caller owns the result and should free() it.
+export function GetBlobPalette() {
+    const palette = Palette.from_hex(palettes[current].data.join(" "));
+    palette.set_name(palettes[current].name);
+    return palette;
+}
+
+// Add a palette loaded from a file and switch to it.
+export function AddPalette(palette) {
+    let data = [];
+    for (let i = 0; i < palette.len(); i++) {
+        data.push(palette.get_hex(i));
     }
 
-    return cols;
+    palettes.push({
+        name: palette.get_name() || "Custom",
+        data: data,
+    });
+    current = palettes.length - 1;
 }
\ No newline at end of file
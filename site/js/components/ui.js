@@ -3,6 +3,7 @@ import ReactDOM from 'react-dom';
 import Button from '@material-ui/core/Button';
 import Slider from '@material-ui/core/Slider';
 import Radio from '@material-ui/core/Radio';
+import Checkbox from '@material-ui/core/Checkbox';
 import RadioGroup from '@material-ui/core/RadioGroup';
 import FormControlLabel from '@material-ui/core/FormControlLabel';
 import FormControl from '@material-ui/core/FormControl';
@@ -14,14 +15,16 @@ import AccordionDetails from '@material-ui/core/AccordionDetails';
 import ExpandMoreIcon from '@material-ui/icons/ExpandMore';
 
 import Paper from '@material-ui/core/Paper';
+import TextField from '@material-ui/core/TextField';
 import Tabs from '@material-ui/core/Tabs';
 import Tab from '@material-ui/core/Tab';
 
 import { createMuiTheme, makeStyles, ThemeProvider } from '@material-ui/core/styles';
 
-import { GetBrush, RenderBrushGraph, ResetOutliner, ResetPaintbrush, ResetSmudger, ResetColorer, SetSize, SetOutlinerHeight} from './brush.js';
-import {StartCapture, ResetCapture, SetBlobCanvasThreshBase, SetBlobCanvasThreshTVar, SetBlobCanvasThreshTMult, Undo, ClearCanvas, FlipCanvas} from "./paint.js";
-import {GetPaletteName, NextPalette, PrevPalette} from './palette.js';
+import { GetBrush, RenderBrushGraph, ResetOutliner, ResetPaintbrush, ResetSmudger, ResetColorer, ResetWobbler, ResetLineWeight, SetSize, SetOutlinerHeight} from './brush.js';
+import {StartCapture, ResetCapture, SetBlobCanvasThreshBase, SetBlobCanvasThreshTVar, SetBlobCanvasThreshTMult, SetBlobCanvasOutlineWidth, SetBlobCanvasSymmetry, SetBlobCanvasSymmetryFolds, SetBlobCanvasWrap, SetBlobCanvasWaveDirection, SetBlobCanvasWaveFrequency, SetBlobCanvasWaveRadial, SetBlobCanvasWaveform, ApplyPalette, Undo, Redo, ClearCanvas, FlipCanvas, FlipCanvasVertical, RotateCanvas, GetCanvasSize, ResizeCanvas, ScaleCanvas, ImportImage, SetSelectTool, SetSelectOp, SelectAll, InvertSelection, Copy, Cut, Paste, FlipPaste, CommitPaste, SaveCanvas, LoadCanvas} from "./paint.js";
+import {GetPaletteName, NextPalette, PrevPalette, AddPalette} from './palette.js';
+import { Waveform, Palette, SymmetryMode, SelectionOp } from "../../node_modules/blobrust/blobrust.js"
 
 const theme = createMuiTheme({
   overrides: {
@@ -153,6 +156,10 @@ function PaintBrush(props) {
           return ResetSmudger();
         case "colorer":
           return ResetColorer();
+        case "wobbler":
+          return ResetWobbler();
+        case "lineweight":
+          return ResetLineWeight();
       }
     }
     
@@ -297,6 +304,88 @@ function PaintBrush(props) {
         </AccordionDetails>
         </Accordion>
         <Accordion>
+        <AccordionSummary
+          expandIcon={<ExpandMoreIcon />}
+          aria-controls="panel1a-content"
+          id="panel1a-header"
+        >
+          <FormControlLabel
+            value="wobbler"
+            aria-label="Wobbler"
+            onClick={(event) => {event.stopPropagation(); setTool("wobbler")}}
+            onFocus={(event) => {event.stopPropagation(); setTool("wobbler")}}
+            control={<Radio />}
+            label="Wobbler"
+          />
+        </AccordionSummary>
+        <AccordionDetails>
+          <div className={classes.root}>
+          <Typography>Size</Typography>
+          <Slider
+              defaultValue={32}
+              aria-labelledby="continuous-slider"
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {SetSize("wobbler", val)}}
+              min={4}
+              max={80}
+          />
+          <Typography>Wobble</Typography>
+          <Slider
+              defaultValue={0}
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {GetBrush("wobbler").set_wobble_amplitude(val / 100)}}
+              min={0}
+              max={400}
+          />
+          <Typography>Offset</Typography>
+          <Slider
+              defaultValue={0}
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {GetBrush("wobbler").set_wobble_phase(val / 100)}}
+              min={0}
+              max={100}
+          />
+          </div>
+        </AccordionDetails>
+        </Accordion>
+        <Accordion>
+        <AccordionSummary
+          expandIcon={<ExpandMoreIcon />}
+          aria-controls="panel1a-content"
+          id="panel1a-header"
+        >
+          <FormControlLabel
+            value="lineweight"
+            aria-label="Line Weight"
+            onClick={(event) => {event.stopPropagation(); setTool("lineweight")}}
+            onFocus={(event) => {event.stopPropagation(); setTool("lineweight")}}
+            control={<Radio />}
+            label="Line Weight"
+          />
+        </AccordionSummary>
+        <AccordionDetails>
+          <div className={classes.root}>
+          <Typography>Size</Typography>
+          <Slider
+              defaultValue={16}
+              aria-labelledby="continuous-slider"
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {SetSize("lineweight", val)}}
+              min={4}
+              max={80}
+          />
+          <Typography>Weight</Typography>
+          <Slider
+              defaultValue={200}
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {GetBrush("lineweight").set_line_weight(val / 100)}}
+              min={0}
+              max={800}
+          />
+          </div>
+        </AccordionDetails>
+        </Accordion>
+        <Accordion>
         <AccordionSummary
           expandIcon={<ExpandMoreIcon />}
           aria-controls="panel1a-content"
@@ -365,11 +454,62 @@ function PaintBrush(props) {
               min={1}
               max={100}
           />
+          <Typography>Outline</Typography>
+          <Slider
+              defaultValue={50}
+              aria-labelledby="continuous-slider"
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {SetBlobCanvasOutlineWidth(val / 1000)}}
+              min={0}
+              max={200}
+          />
+          <Typography variant="h4">Wave</Typography>
+          <br/>
+          <Typography>Direction</Typography>
+          <Slider
+              defaultValue={90}
+              aria-labelledby="continuous-slider"
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {SetBlobCanvasWaveDirection(val * Math.PI / 180)}}
+              min={0}
+              max={360}
+          />
+          <Typography>Waves</Typography>
+          <Slider
+              defaultValue={1}
+              aria-labelledby="continuous-slider"
+              valueLabelDisplay="auto"
+              onChange={ (e, val) => {SetBlobCanvasWaveFrequency(val)}}
+              min={1}
+              max={8}
+          />
+          <FormControlLabel
+            control={<Checkbox onChange={ (e) => {SetBlobCanvasWaveRadial(e.target.checked)}} />}
+            label="Ripple from centre"
+          />
+          <RadioGroup row defaultValue="sine" onChange={ (e) => {
+              switch (e.target.value) {
+                case "sine":
+                  return SetBlobCanvasWaveform(Waveform.Sine);
+                case "triangle":
+                  return SetBlobCanvasWaveform(Waveform.Triangle);
+                case "noise":
+                  return SetBlobCanvasWaveform(Waveform.Noise);
+              }
+            }}>
+            <FormControlLabel value="sine" control={<Radio />} label="Smooth" />
+            <FormControlLabel value="triangle" control={<Radio />} label="Sharp" />
+            <FormControlLabel value="noise" control={<Radio />} label="Bumpy" />
+          </RadioGroup>
           </div>
         </AccordionDetails>
         </Accordion>
+        <SymmetryUI />
+        <SelectionUI />
+        <CanvasSizeUI />
         <PaletteUI />
         <ResetUndoUI />
+        <SaveLoadUI />
         <Typography>
         Source code on <a href="https://github.com/danslocombe/blobpaint/">github</a>.
         </Typography>
@@ -427,10 +567,88 @@ function ExportUI(props) {
   );
 }
 
+function SymmetryUI() {
+  const classSpacing = useStylesSpacing();
+  const setMode = (e) => {
+    switch (e.target.value) {
+      case "off":
+        return SetBlobCanvasSymmetry(SymmetryMode.Off);
+      case "horizontal":
+        return SetBlobCanvasSymmetry(SymmetryMode.Horizontal);
+      case "vertical":
+        return SetBlobCanvasSymmetry(SymmetryMode.Vertical);
+      case "both":
+        return SetBlobCanvasSymmetry(SymmetryMode.Both);
+      case "radial":
+        return SetBlobCanvasSymmetry(SymmetryMode.Radial);
+    }
+  };
+
+  return (
+    <Accordion>
+    <AccordionSummary
+      expandIcon={<ExpandMoreIcon />}
+      aria-controls="panel1a-content"
+      id="panel1a-header"
+    >
+      <Typography>
+        Symmetry
+      </Typography>
+    </AccordionSummary>
+    <AccordionDetails>
+    <div className={classSpacing.root}>
+    <RadioGroup row defaultValue="off" onChange={setMode}>
+      <FormControlLabel value="off" control={<Radio />} label="Off" />
+      <FormControlLabel value="horizontal" control={<Radio />} label="Left / Right" />
+      <FormControlLabel value="vertical" control={<Radio />} label="Top / Bottom" />
+      <FormControlLabel value="both" control={<Radio />} label="Both" />
+      <FormControlLabel value="radial" control={<Radio />} label="Radial" />
+    </RadioGroup>
+    <Typography>Radial Repeats</Typography>
+    <Slider
+        defaultValue={6}
+        aria-labelledby="continuous-slider"
+        valueLabelDisplay="auto"
+        onChange={ (e, val) => {SetBlobCanvasSymmetryFolds(val)}}
+        min={2}
+        max={16}
+    />
+    <FormControlLabel
+        control={<Checkbox onChange={ (e) => {SetBlobCanvasWrap(e.target.checked)}} />}
+        label="Wrap edges (tileable)"
+    />
+    </div>
+      </AccordionDetails>
+      </Accordion>
+  );
+}
+
 function PaletteUI() {
   const [paletteName, setPaletteName] = React.useState(GetPaletteName())
  // const classes = useStyles();
   const classSpacing = useStylesSpacing();
+  const [loadError, setLoadError] = React.useState("");
+
+  // GIMP .gpl or Lospec .hex
+  const loadPalette = (file) => {
+    file.text().then((text) => {
+      try {
+        const palette = Palette.parse(text);
+        if (!palette.get_name()) {
+          palette.set_name(file.name.replace(/\.[^.]*$/, ""));
+        }
+        AddPalette(palette);
+        palette.free();
+        ApplyPalette();
+        setPaletteName(GetPaletteName());
+        setLoadError("");
+      }
+      catch (e) {
+        setLoadError(String(e));
+      }
+    });
+  };
+
   return (
     <Accordion>
     <AccordionSummary
@@ -445,8 +663,116 @@ function PaletteUI() {
     <AccordionDetails>
     <div className={classSpacing.root}>
     <Typography>{paletteName}</Typography>
-    <Button key="0" variant="outlined" color="primary" onClick={(evt) => {PrevPalette(); setPaletteName(GetPaletteName)}}> Prev </Button>
-    <Button key="1" variant="outlined" color="secondary" onClick={(evt) => {NextPalette(); setPaletteName(GetPaletteName)}}> Next </Button>
+    <Button key="0" variant="outlined" color="primary" onClick={(evt) => {PrevPalette(); ApplyPalette(); setPaletteName(GetPaletteName)}}> Prev </Button>
+    <Button key="1" variant="outlined" color="secondary" onClick={(evt) => {NextPalette(); ApplyPalette(); setPaletteName(GetPaletteName)}}> Next </Button>
+    <Button key="2" variant="outlined" component="label"> Load
+      <input type="file" accept=".gpl,.hex,.txt" hidden onChange={(evt) => {if (evt.target.files.length > 0) {loadPalette(evt.target.files[0])}}} />
+    </Button>
+    <Typography color="error">{loadError}</Typography>
+    </div>
+      </AccordionDetails>
+      </Accordion>
+  );
+}
+
+function SelectionUI() {
+  const classSpacing = useStylesSpacing();
+  const setOp = (e) => {
+    switch (e.target.value) {
+      case "replace":
+        return SetSelectOp(SelectionOp.Replace);
+      case "add":
+        return SetSelectOp(SelectionOp.Add);
+      case "subtract":
+        return SetSelectOp(SelectionOp.Subtract);
+      case "intersect":
+        return SetSelectOp(SelectionOp.Intersect);
+    }
+  };
+
+  return (
+    <Accordion>
+    <AccordionSummary
+      expandIcon={<ExpandMoreIcon />}
+      aria-controls="panel1a-content"
+      id="panel1a-header"
+    >
+      <Typography>
+        Tools
+      </Typography>
+    </AccordionSummary>
+    <AccordionDetails>
+    <div className={classSpacing.root}>
+    <RadioGroup row defaultValue="paint" onChange={(e) => SetSelectTool(e.target.value == "paint" ? null : e.target.value)}>
+      <FormControlLabel value="paint" control={<Radio />} label="Paint" />
+      <FormControlLabel value="rect" control={<Radio />} label="Rectangle" />
+      <FormControlLabel value="ellipse" control={<Radio />} label="Ellipse" />
+      <FormControlLabel value="lasso" control={<Radio />} label="Lasso" />
+      <FormControlLabel value="wand" control={<Radio />} label="Wand" />
+      <FormControlLabel value="fill" control={<Radio />} label="Fill" />
+    </RadioGroup>
+    <RadioGroup row defaultValue="replace" onChange={setOp}>
+      <FormControlLabel value="replace" control={<Radio />} label="Replace" />
+      <FormControlLabel value="add" control={<Radio />} label="Add" />
+      <FormControlLabel value="subtract" control={<Radio />} label="Subtract" />
+      <FormControlLabel value="intersect" control={<Radio />} label="Intersect" />
+    </RadioGroup>
+    <Button variant="outlined" color="primary" onClick={(evt) => {SelectAll()}}> Select All </Button>
+    <Button variant="outlined" color="primary" onClick={(evt) => {InvertSelection()}}> Invert </Button>
+    <Button variant="outlined" color="primary" onClick={(evt) => {Copy()}}> Copy </Button>
+    <Button variant="outlined" color="primary" onClick={(evt) => {Cut()}}> Cut </Button>
+    <Button variant="outlined" color="primary" onClick={(evt) => {Paste()}}> Paste </Button>
+    <Button variant="outlined" color="primary" onClick={(evt) => {FlipPaste()}}> Flip Paste </Button>
+    <Button variant="outlined" color="primary" onClick={(evt) => {CommitPaste()}}> Done </Button>
+    </div>
+      </AccordionDetails>
+      </Accordion>
+  );
+}
+
+function CanvasSizeUI() {
+  const classSpacing = useStylesSpacing();
+  const [size, setSize] = React.useState(GetCanvasSize());
+  const [lightOnDark, setLightOnDark] = React.useState(false);
+  const [importError, setImportError] = React.useState("");
+  const importImage = (file) => {
+    ImportImage(file, lightOnDark).then((error) => {
+      setImportError(error);
+      setSize(GetCanvasSize());
+    });
+  };
+  const apply = (f) => {
+    if (!f(size[0], size[1])) {
+      // Rejected, show what the canvas actually is.
+      setSize(GetCanvasSize());
+    }
+  };
+
+  return (
+    <Accordion>
+    <AccordionSummary
+      expandIcon={<ExpandMoreIcon />}
+      aria-controls="panel1a-content"
+      id="panel1a-header"
+    >
+      <Typography>
+        Canvas Size
+      </Typography>
+    </AccordionSummary>
+    <AccordionDetails>
+    <div className={classSpacing.root}>
+    <TextField label="Width" type="number" value={size[0]} onChange={(e) => setSize([parseInt(e.target.value) || 0, size[1]])} />
+    <TextField label="Height" type="number" value={size[1]} onChange={(e) => setSize([size[0], parseInt(e.target.value) || 0])} />
+    <Button variant="outlined" color="primary" onClick={(evt) => {apply(ResizeCanvas)}}> Resize </Button>
+    <Button variant="outlined" color="primary" onClick={(evt) => {apply(ScaleCanvas)}}> Scale </Button>
+    <Button variant="outlined" component="label"> Import Image
+      <input type="file" accept="image/*" hidden onChange={(evt) => {if (evt.target.files.length > 0) {importImage(evt.target.files[0])}}} />
+    </Button>
+    <FormControlLabel
+        control={<Checkbox checked={lightOnDark} onChange={(e) => setLightOnDark(e.target.checked)} />}
+        label="Light on dark"
+    />
+    <Typography color="error">{importError}</Typography>
     </div>
       </AccordionDetails>
       </Accordion>
@@ -460,9 +786,34 @@ function ResetUndoUI() {
     <div className={classes.root}>
     <div className={classSpacing.root}>
     <Button key="0" variant="outlined" color="primary" onClick={(evt) => {FlipCanvas()}}> FLIP </Button>
-    <Button key="1" variant="outlined" color="primary" onClick={(evt) => {Undo()}}> Undo </Button>
-    <Button key="2" variant="outlined" color="secondary" onClick={(evt) => {ClearCanvas()}}> Clear </Button>
+    <Button key="1" variant="outlined" color="primary" onClick={(evt) => {FlipCanvasVertical()}}> FLIP V </Button>
+    <Button key="2" variant="outlined" color="primary" onClick={(evt) => {RotateCanvas()}}> Rotate </Button>
+    <Button key="3" variant="outlined" color="primary" onClick={(evt) => {Undo()}}> Undo </Button>
+    <Button key="4" variant="outlined" color="primary" onClick={(evt) => {Redo()}}> Redo </Button>
+    <Button key="5" variant="outlined" color="secondary" onClick={(evt) => {ClearCanvas()}}> Clear </Button>
+    </div>
+    </div>
+  );
+}
+
+function SaveLoadUI() {
+  const classes = useStyles();
+  const classSpacing = useStylesSpacing();
+  const [loadError, setLoadError] = React.useState("");
+  const loadCanvas = (file) => {
+    LoadCanvas(file).then((error) => {
+      setLoadError(error);
+    });
+  };
+  return (
+    <div className={classes.root}>
+    <div className={classSpacing.root}>
+    <Button key="0" variant="outlined" color="primary" onClick={(evt) => {SaveCanvas()}}> Save </Button>
+    <Button key="1" variant="outlined" color="primary" component="label"> Load
+      <input type="file" accept=".blob" hidden onChange={(evt) => {if (evt.target.files.length > 0) {loadCanvas(evt.target.files[0])}}} />
+    </Button>
     </div>
+    <Typography>{loadError}</Typography>
     </div>
   );
 }
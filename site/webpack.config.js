@@ -3,7 +3,6 @@ const path = require('path');
 
 module.exports = {
   entry: {
-    "gif.worker": "./node_modules/gif.js/dist/gif.worker.js",
     index: "./js/bootstrap.js",
   },
   output: {